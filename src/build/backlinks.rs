@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use crate::build::context::Context;
+use crate::constants::MyResult;
+use crate::build::pages::Page;
+use crate::build::compile::html_escape;
+
+/// A `{}` link from one page to another page or label
+#[derive(Debug, Clone)]
+pub struct Reference {
+    /// Path to the markdown file containing the link, starting with text/
+    pub source: String,
+    /// The heading the link appeared under
+    pub heading: Option<String>,
+    /// The link to the target
+    pub href: String,
+    /// The display name of the target, such as "Eq. 3"
+    pub label: String,
+}
+
+impl Reference {
+    /// The link to the page containing the target, without any anchor
+    fn target_page(&self) -> &str {
+        self.href.split('#').next().unwrap_or(&self.href)
+    }
+}
+
+/// Every reference made in the wiki, grouped by the page they point to.
+pub struct Backlinks {
+    map: BTreeMap<String, Vec<Reference>>,
+}
+
+impl Backlinks {
+    pub fn new() -> Backlinks {
+        Backlinks { map: BTreeMap::new() }
+    }
+
+    pub fn add(&mut self, references: Vec<Reference>) {
+        for reference in references {
+            self.map.entry(reference.target_page().to_owned()).or_default().push(reference);
+        }
+    }
+
+    /// Returns true if any other page links to `page` or its labels
+    pub fn is_linked(&self, page: &Page, ctx: &Context) -> MyResult<bool> {
+        let link = ctx.link(&page.html_path())?;
+        Ok(match self.map.get(&link) {
+            Some(r) => r.iter().any(|reference| reference.source != page.local_path),
+            None => false,
+        })
+    }
+
+    /// The "Linked from" section listing the pages which link to `page`.
+    pub fn render(&self, page: &Page, pages: &[Page], ctx: &Context) -> MyResult<String> {
+        let link = ctx.link(&page.html_path())?;
+        let references = match self.map.get(&link) {
+            Some(r) => r,
+            None => return Ok(String::new()),
+        };
+
+        // Group the references by source page and heading, keeping the order they were made in and
+        // listing each label once
+        let mut groups: Vec<(&str, &Option<String>, Vec<&str>)> = Vec::new();
+        for reference in references {
+            if reference.source == page.local_path {
+                continue;
+            }
+            let label = if reference.href == link { None } else { Some(reference.label.as_str()) };
+            match groups.iter_mut().find(|(s, h, _)| *s == reference.source && **h == reference.heading) {
+                Some((_, _, labels)) => labels.extend(label.filter(|l| !labels.contains(l))),
+                None => groups.push((&reference.source, &reference.heading, label.into_iter().collect())),
+            }
+        }
+        if groups.is_empty() {
+            return Ok(String::new());
+        }
+
+        let mut out = "<div class=\"backlinks\"><h3>Linked from</h3><ul>".to_owned();
+        for (source, heading, labels) in groups {
+            let source_page = match pages.iter().find(|p| p.local_path == source) {
+                Some(p) => p,
+                None => continue,
+            };
+            out.push_str(&format!("<li><a href=\"{}\">{}</a>", ctx.link(&source_page.html_path())?, html_escape(&source_page.title)));
+            if let Some(h) = heading {
+                out.push_str(&format!(" &middot; {}", html_escape(h)));
+            }
+            if !labels.is_empty() {
+                out.push_str(&format!(" <span class=\"backlink-labels\">({})</span>", html_escape(&labels.join(", "))));
+            }
+            out.push_str("</li>");
+        }
+        out.push_str("</ul></div>");
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(href: &str) -> Reference {
+        Reference { source: "text/a.md".to_owned(), heading: None, href: href.to_owned(), label: String::new() }
+    }
+
+    #[test]
+    fn target_page_drops_the_anchor() {
+        assert_eq!(reference("https://example.com/html/spin.html#eq:x").target_page(), "https://example.com/html/spin.html");
+        assert_eq!(reference("https://example.com/html/notes.html/spin.html").target_page(), "https://example.com/html/notes.html/spin.html");
+        assert_eq!(reference("https://example.com/html/spin.html").target_page(), "https://example.com/html/spin.html");
+    }
+}
@@ -0,0 +1,69 @@
+use clap::Parser;
+use crate::constants::MyResult;
+use crate::diagnostics::{Diagnostic, codes};
+use crate::build::file_queue::FileQueue;
+use crate::build::compile_tree::Node;
+use crate::build::report::Report;
+use crate::build::index::has_generated_index;
+use crate::build::context::Context;
+
+#[derive(Parser)]
+pub struct CheckSettings {
+    /// Check the public build, which leaves out drafts
+    #[arg(short, long)]
+    public: bool,
+    /// Fail if there are any warnings
+    #[arg(long)]
+    deny_warnings: bool,
+    /// Verbosity
+    #[arg(long, short)]
+    verbose: bool,
+}
+
+impl CheckSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let mut ctx = Context::load(chosen, self.public, true)?;
+        if self.public && ctx.root.public_url.is_empty() {
+            return Err("You must first set the public url".into());
+        }
+
+        let mut report = Report::new();
+        let compile_tree = Node::new(&ctx)?;
+        if crate::is_verbose() {
+            println!("Checking {} files", compile_tree.size());
+        }
+        let pages = compile_tree.pages(&ctx, &mut report);
+        ctx.ref_map = compile_tree.ref_map(&pages, &ctx, &mut report)?;
+        // Ignored sections were only read for their labels
+        let pages = pages.into_iter().filter(|p| !ctx.settings(&p.local_path).ignore).collect::<Vec<_>>();
+
+        // Compile into a queue which is never written
+        let mut file_queue = FileQueue::new();
+        let backlinks = compile_tree.compile(&mut file_queue, &pages, &ctx, &mut report)?;
+
+        for page in &pages {
+            if self.public && page.draft {
+                continue;
+            }
+            if page.local_path == "text/_toc.md" || backlinks.is_linked(page, &ctx)? {
+                continue;
+            }
+            let toc_path = format!("{}/_toc.md", page.section_path());
+            if !page.is_toc() && has_generated_index(&toc_path, &ctx)? {
+                continue;
+            }
+            report.add(Diagnostic::warning("No other page links to this page")
+                .code(codes::ORPHAN_PAGE)
+                .in_file(&page.local_path)
+                .help("Link to it from another page, or add it to a section index"));
+        }
+
+        report.print();
+        println!("Checked {} files: {} errors, {} warnings", pages.len(), report.errors(), report.warnings());
+        if report.errors() > 0 || (self.deny_warnings && report.warnings() > 0) {
+            return Err("Check failed".into());
+        }
+        Ok(())
+    }
+}
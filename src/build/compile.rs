@@ -1,14 +1,23 @@
 use crate::root::Root;
 
-use std::fs::File;
+use std::fs;
 use crate::build::file_queue::FileQueue;
-use std::io::{self, BufRead};
 use std::path::Path;
 use crate::constants::MyResult;
+use crate::diagnostics::{Diagnostic, codes};
 use crate::build::refs::RefMap;
 use chrono::{Datelike, Month};
 use num_traits::FromPrimitive;
 use super::links::*;
+use super::pages::{Page, html_path, plain_text};
+use super::backlinks::Reference;
+use super::report::Report;
+use super::sitemap::canonical_link;
+use super::search::search_box;
+use super::index::{index_directive, section_index};
+use super::front_matter::FrontMatter;
+use super::tags::tag_chips;
+use super::context::Context;
 
 
 
@@ -44,7 +53,7 @@ pub struct Command {
     pub c_type: CommandTypes,
 }
 
-pub struct ParseState {
+pub struct ParseState<'a> {
     list: Option<ListType>,
     previous_paragraph: bool,
     section_open: bool,
@@ -53,6 +62,20 @@ pub struct ParseState {
     fig_num: u32,
     footnotes: Vec<String>,
     pub eq_num: usize,
+    /// Path to the markdown file being compiled, starting with text/
+    pub source: String,
+    /// The most recent heading, used to give context to references
+    pub heading: Option<String>,
+    pub references: Vec<Reference>,
+    /// Set when checking the wiki rather than building it
+    pub check: bool,
+    /// Warnings about the current line, and when checking, the errors which did not stop it from
+    /// being parsed
+    pub diagnostics: Vec<Diagnostic>,
+    /// Set when rendering outside a wiki. Images are linked under this URL rather than copied.
+    pub asset_url: Option<String>,
+    /// Set when compiling a page of a wiki
+    pub ctx: Option<&'a Context>,
 }
 impl Command {
     pub fn new() -> Command {
@@ -107,11 +130,7 @@ impl Command {
             _ => self.c_type
             
         };
-        if let CommandTypes::NoCommand = self.c_type {
-            false
-        } else {
-            true
-        }
+        !matches!(self.c_type, CommandTypes::NoCommand)
     }
 
     fn prefix(&self) -> String {
@@ -153,11 +172,11 @@ impl Command {
 }
 
 
-impl ParseState {
-    fn new(path: &str) -> ParseState {
+impl<'a> ParseState<'a> {
+    pub fn new(path: &str, check: bool) -> ParseState<'a> {
         let mut local_path = Path::new(path).parent().expect("Path had no parent").to_str().expect("Could not extract path").to_owned();
         if local_path.starts_with("./") {
-            local_path = (&local_path[2..]).to_owned();
+            local_path = local_path[2..].to_owned();
         }
         ParseState {
             list: None,
@@ -168,9 +187,31 @@ impl ParseState {
             fig_num: 0,
             footnotes: Vec::new(),
             eq_num: 1,
+            source: path.to_owned(),
+            heading: None,
+            references: Vec::new(),
+            check,
+            diagnostics: Vec::new(),
+            asset_url: None,
+            ctx: None,
+        }
+    }
+
+    /// The state for compiling the page at `path` of a wiki
+    pub fn in_wiki(path: &str, ctx: &'a Context) -> ParseState<'a> {
+        let mut parse_state = ParseState::new(path, ctx.check);
+        parse_state.ctx = Some(ctx);
+        parse_state
+    }
+
+    /// The context of the wiki, which is needed for images and applets
+    pub fn context(&self) -> MyResult<&'a Context> {
+        match self.ctx {
+            Some(c) => Ok(c),
+            None => Err("Images and applets can only be compiled inside a wiki".into()),
         }
     }
-    fn terminal(self, file_queue: &mut FileQueue) -> String{
+    pub fn terminal(self, file_queue: &mut FileQueue) -> String{
         let mut out = if let Some(l) = self.list {
             match l {
                 ListType::Ordered => "</ol>".to_owned(),
@@ -194,15 +235,24 @@ impl ParseState {
 
     pub fn move_img(&mut self, link_text: &str) -> MyResult<()> {
         // self.local_path already contains the text directory.
-        let path_from = Root::get_path_from_local(&format!("{}/{}", self.local_path, link_text))?;
-        let path_to = Root::get_path_from_local(&format!("html/{}/{}", &self.local_path[5..], link_text))?;
+        let ctx = self.context()?;
+        let path_from = ctx.path(&format!("{}/{}", self.local_path, link_text));
+        let path_to = self.img_html_path(link_text)["html/".len()..].to_owned();
         if !Path::new(&path_from).exists() {
-            return Err(format!("Could not find image {}", path_from))
+            return Err(Diagnostic::error(format!("Could not find image {}", path_from)).code(codes::MISSING_IMAGE))
         }
         self.imgs.push((path_from, path_to));
         Ok(())
     }
 
+    /// Path starting with html/ which an image linked from this page is copied to
+    pub fn img_html_path(&self, link_text: &str) -> String {
+        match self.local_path.get(5..) {
+            Some(dir) => format!("html/{}/{}", dir, link_text),
+            None => format!("html/{}", link_text),// Pages at the top of text/
+        }
+    }
+
     pub fn figure(&mut self) -> u32 {
         self.fig_num += 1;
         self.fig_num
@@ -211,43 +261,30 @@ impl ParseState {
     fn footnote(&mut self, text: String, c: char) -> String {
         // Return display text and add footnote text to vector
         self.footnotes.push(text);
-        return format!("<sup><a href=\"#footnote{num}\">{num}</a></sup>{c}", num=self.footnotes.len(), c=c)
+        format!("<sup><a href=\"#footnote{num}\">{num}</a></sup>{c}", num=self.footnotes.len(), c=c)
     }
 }
 
-fn get_footer() -> MyResult<String> {
-    let root = Root::summon()?;
-    let now = chrono::Utc::now();
+fn get_footer(root: &Root) -> MyResult<String> {
+    let now = root.get_updated_date();
     Ok(format!("Copyright &copy; {year} Jack Dinsmore. &emsp;Updated {month} {day}. &emsp;Version {maj}.{min}",
     year=now.year(), month=Month::from_u32(now.month()).expect("Month was invalid").name(), day=now.day(),
         maj=root.wikid_version_major, min=root.wikid_version_minor))
 }
 
-/// Turn code in the file "path" into some compiled code in the return type.
-pub fn compile_file<'a>(local_path: &'a str, file_queue: &mut FileQueue, ref_map: &RefMap, public: bool) -> MyResult<String> {
-    // Get the parent path, excluding the text/
-
-    let global_path = Root::get_path_from_local(local_path)?;
-    let file = match File::open(&global_path) {
-        Ok(f) => f,
-        Err(_) => return Err(format!("Compile tree was corrupted in main: path {}", global_path))
-    };
-
-    let root = Root::summon()?;
-    let section_name = root.get_section(local_path);
-    let root_toc_path = root.get_link_from_local("html/index.html", public)?;
-
-    let header = if section_name != "text" {
-        let sec_toc_path = root.get_link_from_local(&format!("html/{}/index.html", section_name), public)?;
-        format!("<h2><a href=\"{}\">Home</a> > <a href=\"{}\">{}</a></h2>", root_toc_path, sec_toc_path, section_name)
-    } else {
-        format!("<h2><a href=\"{}\">Home</a></h2>", root_toc_path)
-    };
-    let css_name = root.get_link_from_local(&format!("html/css/{}.css", section_name), public)?;
+/// Escape text for use inside an HTML element or attribute.
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
 
-    let mut compiled_text : String = format!(r#"<html>
+/// The opening of an HTML page, up to and including the navigation header. `title` is escaped.
+pub fn page_start(css_name: &str, title: &str, meta: &str, header: &str) -> String {
+    format!(r#"<html>
 <head>
-    <meta charset="utf-8">
+    <meta charset="utf-8">{meta}
     <link rel="stylesheet" type = "text/css" href = "{css_name}">
     <script src="https://polyfill.io/v3/polyfill.min.js?features=es6"></script>
     <script id="MathJax-script" async
@@ -271,11 +308,109 @@ window.MathJax = {{
     }}
 }}
     </script>
-    <title>{all_name}</title>
-</head><body><div id="content">{header}"#,
-    css_name=css_name, all_name=root.name, header=header);
+    <script>
+try {{
+    var theme = localStorage.getItem("wikid-theme");
+    if (theme) {{
+        document.documentElement.dataset.theme = theme;
+    }}
+}} catch (e) {{}}
+    </script>
+    <title>{title}</title>
+</head><body><button id="theme-toggle" title="Switch between light and dark themes">&#9681;</button><div id="content">{header}"#,
+    css_name=css_name, title=html_escape(title), header=header, meta=meta)
+}
+
+/// The footer and scripts which close an HTML page.
+pub fn page_end(root: &Root) -> MyResult<String> {
+    Ok(format!("</div><div id=\"footer\">\n{}</div></body>
+<script>
+var coll = document.getElementsByClassName(\"collapsible\");
+var i;
+
+for (i = 0; i < coll.length; i++) {{
+    coll[i].addEventListener(\"click\", function() {{
+        this.classList.toggle(\"active\");
+        var content = this.nextElementSibling;
+        if (content.style.maxHeight === \"0px\"){{
+            content.style.maxHeight = content.scrollHeight+\"px\";
+        }} else {{
+            content.style.maxHeight = \"0px\";
+        }}
+    }});
+}}
+
+document.getElementById(\"theme-toggle\").addEventListener(\"click\", function() {{
+    var current = getComputedStyle(document.documentElement).getPropertyValue(\"--theme\").trim();
+    var theme = current === \"dark\" ? \"light\" : \"dark\";
+    document.documentElement.dataset.theme = theme;
+    try {{
+        localStorage.setItem(\"wikid-theme\", theme);
+    }} catch (e) {{}}
+}});
+</script></html>\n", get_footer(root)?))
+}
+
+/// Turn code in the file "path" into some compiled code in the return type, along with the
+/// references it makes to other pages. The page is not closed, so that backlinks can be added
+/// once every page has been compiled; append `page_end` to finish it.
+pub fn compile_file(local_path: &str, file_queue: &mut FileQueue, pages: &[Page], ctx: &Context, report: &mut Report) -> MyResult<(String, Vec<Reference>)> {
+    // Get the parent path, excluding the text/
+
+    let global_path = ctx.path(local_path);
+    let text = match fs::read_to_string(&global_path) {
+        Ok(t) => t,
+        Err(_) => return Err(format!("Compile tree was corrupted in main: path {}", global_path).into())
+    };
+    let (front_matter, front_lines) = match FrontMatter::parse(&text) {
+        Ok(f) => f,
+        Err(d) => return Err(d.in_file(local_path))
+    };
 
-    let mut parse_state = ParseState::new(local_path);
+    let root = &ctx.root;
+    let public = ctx.public;
+    let ref_map = &ctx.ref_map;
+    let title = match &front_matter.title {
+        Some(t) => format!("{} - {}", t, root.name),
+        None => root.name.clone(),
+    };
+    let root_toc_path = ctx.link("html/index.html")?;
+
+    // Link to the index of every section containing the page, outermost first
+    let mut header = format!("<h2><a href=\"{}\">Home</a>", root_toc_path);
+    let parent = Path::new(local_path).parent().and_then(|p| p.to_str()).unwrap_or("text");
+    if let Some(dirs) = parent.strip_prefix("text/") {
+        let mut dir = String::new();
+        for name in dirs.split('/') {
+            dir = match dir.is_empty() {
+                true => name.to_owned(),
+                false => format!("{}/{}", dir, name),
+            };
+            header.push_str(&format!(" > <a href=\"{}\">{}</a>", ctx.link(&format!("html/{}/index.html", dir))?, html_escape(name)));
+        }
+    }
+    header.push_str("</h2>");
+    header.push_str(&search_box(ctx)?);
+    header.push_str(&tag_chips(&front_matter.tags, ctx)?);
+    if front_matter.draft || ctx.settings(local_path).draft {
+        header.push_str("<div class=\"draft-banner\">DRAFT</div>");
+    }
+    let css_name = ctx.link(&ctx.css_path(local_path))?;
+
+    let mut meta = front_matter.meta_tags();
+    if front_matter.summary.is_none() {
+        if let Some(d) = ctx.settings(local_path).description {
+            meta.push_str(&format!("\n    <meta name=\"description\" content=\"{}\">", html_escape(&d)));
+        }
+    }
+    if public {
+        meta.push_str(&format!("\n    <link rel=\"alternate\" type=\"application/atom+xml\" title=\"{}\" href=\"{}\">",
+            html_escape(&root.name), ctx.link("html/feed.xml")?));
+        meta.push_str(&canonical_link(&html_path(local_path), root)?);
+    }
+    let mut compiled_text = page_start(&css_name, &title, &meta, &header);
+
+    let mut parse_state = ParseState::in_wiki(local_path, ctx);
     let local_entire_parent_path = &Path::new(local_path).parent().unwrap().to_str().unwrap().to_owned();
     let local_parent_path = if local_entire_parent_path.len() <= 4 {
         None
@@ -286,37 +421,64 @@ window.MathJax = {{
     // Write header material
 
     // Write center material
-    for (line_num, line) in io::BufReader::new(file).lines().enumerate() {
-        compiled_text.push_str(&match parse_line(line.expect("Error in reading files"), ref_map, &mut parse_state, public, local_parent_path) {
+    let is_toc = local_path.ends_with("_toc.md");
+    let mut is_empty = true;
+    for (line_num, line) in text.lines().enumerate().skip(front_lines) {
+        if !line.trim().is_empty() {
+            is_empty = false;
+        }
+        if is_toc {
+            if let Some(sort) = index_directive(line) {
+                // Close any open paragraph or list before the index
+                compiled_text.push_str(&parse_line(String::new(), ref_map, &mut parse_state, public, local_parent_path)?);
+                compiled_text.push_str(&match section_index(local_path, pages, sort, ctx) {
+                    Ok(l) => l,
+                    Err(d) => {
+                        report.add(d.in_file(local_path).at_line(line_num+1, line));
+                        String::new()
+                    }
+                });
+                compiled_text.push('\n');
+                continue;
+            }
+            if public && is_draft_entry(line, ref_map, local_parent_path) {
+                // Drafts are left out of public builds, and so are their entries
+                continue;
+            }
+        }
+        compiled_text.push_str(&match parse_line(line.to_owned(), ref_map, &mut parse_state, public, local_parent_path) {
             Ok(l) => l,
-            Err(m) => return Err(format!("File {} line {}: {}", local_path, line_num+1, m)),
+            Err(d) => {
+                report.add(d.in_file(local_path).at_line(line_num+1, line));
+                String::new()
+            }
         });
-        compiled_text.push_str("\n");
+        compiled_text.push('\n');
+        for diagnostic in parse_state.diagnostics.drain(..) {
+            report.add(diagnostic.in_file(local_path).at_line(line_num+1, line));
+        }
     }
+    let references = std::mem::take(&mut parse_state.references);
     compiled_text.push_str(&parse_state.terminal(file_queue));
+    if is_toc && is_empty {
+        // Empty tables of contents list the pages in the section
+        compiled_text.push_str(&section_index(local_path, pages, "", ctx)?);
+    }
 
-    // Write footer material
-    
-    compiled_text += "</div><div id=\"footer\">\n";
-    compiled_text += &get_footer()?;
-    compiled_text += "</div></body>
-<script>
-var coll = document.getElementsByClassName(\"collapsible\");
-var i;
+    Ok((compiled_text, references))
+}
 
-for (i = 0; i < coll.length; i++) {{
-    coll[i].addEventListener(\"click\", function() {{
-        this.classList.toggle(\"active\");
-        var content = this.nextElementSibling;
-        if (content.style.maxHeight === \"0px\"){{
-            content.style.maxHeight = content.scrollHeight+\"px\";
-        }} else {{
-            content.style.maxHeight = \"0px\";
-        }}
-    }});
-}}
-</script></html>\n";
-    Ok(compiled_text)
+/// True if `line` is an entry of a table of contents, such as `* [Name]{path/_toc}`, whose link
+/// points to a draft
+fn is_draft_entry(line: &str, ref_map: &RefMap, local_path: Option<&str>) -> bool {
+    let entry = line.trim_start();
+    if !entry.starts_with("* ") && !entry.starts_with("- ") {
+        return false;
+    }
+    match (entry.find('{'), entry.find('}')) {
+        (Some(start), Some(end)) if start < end => ref_map.is_draft(&entry[start+1..end], local_path),
+        _ => false,
+    }
 }
 
 struct QuoteTracker {
@@ -342,17 +504,13 @@ impl QuoteTracker {
                 '“'
             }
         } else {
-            if c == ' ' || c == '\n' {
-                self.letter_before = false;
-            } else {
-                self.letter_before = true;
-            }
+            self.letter_before = !(c == ' ' || c == '\n');
             c
         }
     }
 }
 
-fn parse_line(uncompiled_line: String, ref_map: &RefMap, parse_state: &mut ParseState, public: bool, local_path: Option<&str>) -> MyResult<String> {
+pub fn parse_line(uncompiled_line: String, ref_map: &RefMap, parse_state: &mut ParseState, public: bool, local_path: Option<&str>) -> MyResult<String> {
     let mut escaped = false;
     let mut possible_link_stack = vec![PossibleLink::new()];// Stack, where the back is the top
     let mut result = "".to_owned();
@@ -361,14 +519,17 @@ fn parse_line(uncompiled_line: String, ref_map: &RefMap, parse_state: &mut Parse
     let mut modifiers = Modifiers::new();
     let mut quote_tracker = QuoteTracker::new();
 
-    if uncompiled_line.is_empty(){
-        if parse_state.previous_paragraph {
-            parse_state.previous_paragraph = false;
-            return Ok("</p>".to_owned());
-        }
+    if uncompiled_line.starts_with('#') {
+        parse_state.heading = Some(plain_text(uncompiled_line.trim_start_matches('#')).trim().to_owned());
     }
 
-    for c in uncompiled_line.chars() {
+    if uncompiled_line.is_empty() && parse_state.previous_paragraph {
+        parse_state.previous_paragraph = false;
+        return Ok("</p>".to_owned());
+    }
+
+    for (i, c) in uncompiled_line.chars().enumerate() {
+        let column = i + 1;
         let c = quote_tracker.handle_quote(c);
         if command.parse_command(c) {
             continue;
@@ -405,37 +566,59 @@ fn parse_line(uncompiled_line: String, ref_map: &RefMap, parse_state: &mut Parse
                     let possible_link = unsafe {
                         &mut *possible_link_stack_ptr.add(link_len-1)
                     };
-                    let add_output = possible_link.try_add(c);
+                    let add_output = match possible_link.try_add(c, column) {
+                        Ok(r) => r,
+                        Err(d) if parse_state.check => {
+                            // Leave the character out of the link and keep parsing
+                            parse_state.diagnostics.push(d);
+                            LinkReturn::Pushed
+                        },
+                        Err(d) => return Err(d),
+                    };
                     match add_output.clone() { // Try a link
                         LinkReturn::Pushed => (),
                         LinkReturn::Child => {
                             let mut new_link = PossibleLink::new();
-                            new_link.try_add(c);
+                            new_link.try_add(c, column)?;
                             possible_link_stack.push(new_link);
                         },
                         LinkReturn::Failed(s) => result.push_str(&s),
                         LinkReturn::Footnote(s) => result.push_str(&parse_state.footnote(s, c)),
                         LinkReturn::Done => {
+                            let (link_column, link_length) = possible_link.span();
                             match parent_link {
                                 None => {
                                     // This is concluding the outermost link
                                     let output_str = match command.c_type {
-                                        CommandTypes::Image => possible_link.make_img(parse_state, public)?,
-                                        CommandTypes::Applet => possible_link.make_applet(public)?,
-                                        _ => possible_link.make(ref_map, local_path)?
+                                        CommandTypes::Image => possible_link.make_img(parse_state, public),
+                                        CommandTypes::Applet => possible_link.make_applet(parse_state, public),
+                                        _ => possible_link.make(ref_map, parse_state, local_path)
+                                    };
+                                    match output_str {
+                                        Ok(s) => result.push_str(&s),
+                                        Err(d) if parse_state.check => {
+                                            // Report every problem on the line rather than only the first
+                                            parse_state.diagnostics.push(d.at_column(link_column, link_length));
+                                            *possible_link = PossibleLink::new();
+                                        },
+                                        Err(d) => return Err(d.at_column(link_column, link_length)),
                                     };
-                                    result.push_str(&output_str);
                                 },
                                 Some(parent_link) => {
                                     // This is concluding an inner link, which cannot be an image or applet. Add the text to the display text of the inner link
-                                    let output_str = possible_link.make(ref_map, local_path)?;
-                                    parent_link.display_text.push_str(&output_str);
+                                    match possible_link.make(ref_map, parse_state, local_path) {
+                                        Ok(s) => parent_link.display_text.push_str(&s),
+                                        Err(d) if parse_state.check => parse_state.diagnostics.push(d.at_column(link_column, link_length)),
+                                        Err(d) => return Err(d.at_column(link_column, link_length)),
+                                    };
                                 }
                             };
                         },
-                        LinkReturn::Pass =>  match modifiers.check(c)? {
-                            Some(s) => result.push_str(&s),
-                            None => result.push(c)
+                        LinkReturn::Pass =>  match modifiers.check(c, column) {
+                            Ok(Some(s)) => result.push_str(&s),
+                            Ok(None) => result.push(c),
+                            Err(d) if parse_state.check => parse_state.diagnostics.push(d),
+                            Err(d) => return Err(d),
                         }// Check if it's a bold modifier
                     }
                     match &add_output {
@@ -458,6 +641,12 @@ fn parse_line(uncompiled_line: String, ref_map: &RefMap, parse_state: &mut Parse
             };
         }
     }
+    if let Some((m, column)) = modifiers.unclosed() {
+        parse_state.diagnostics.push(Diagnostic::warning(format!("Modifier {} was not closed", m))
+            .code(codes::UNCLOSED_MODIFIER)
+            .at_column(column, 1)
+            .help(format!("Add a closing {}, or escape it as \\{}", m, m)));
+    }
     if let CommandTypes::Header(i) = command.c_type {
         if i == 1 || i == 2 {
             if parse_state.section_open {
@@ -491,10 +680,8 @@ fn parse_line(uncompiled_line: String, ref_map: &RefMap, parse_state: &mut Parse
     }
     else {
         match parse_state.list {
-            Some(old_type) => match old_type{
-                ListType::Ordered => before = format!("</ol>{}", before),
-                ListType::Unordered => before = format!("</ul>{}", before)
-            },
+            Some(ListType::Ordered) => before = format!("</ol>{}", before),
+            Some(ListType::Unordered) => before = format!("</ul>{}", before),
             None => ()
         };
         parse_state.list = None;
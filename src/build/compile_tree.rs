@@ -1,14 +1,22 @@
-use crate::root::Root;
 
 use crate::constants::MyResult;
 use crate::build::refs::RefMap;
 use crate::build::file_queue::FileQueue;
-use crate::build::compile::compile_file;
+use crate::build::compile::{compile_file, page_end};
+use crate::build::backlinks::Backlinks;
+use crate::build::report::Report;
+use crate::build::pages::Page;
+use crate::build::tags::build_tag_pages;
+use crate::build::feed::build_feeds;
+use crate::build::sitemap::build_sitemap;
+use crate::build::redirects::build_redirects;
+use crate::build::search::build_search;
+use crate::build::context::Context;
 use std::fs;
 
 #[derive(PartialEq, Debug)]
 pub struct Node {
-    children: Vec<Box<Node>>,
+    children: Vec<Node>,
     name: String,
     is_leaf: bool,
 }
@@ -28,7 +36,7 @@ impl<'a> TreeIter<'a> {
             node = &node.children[0];
         }
         path.push(node);
-        return Self {new: true, path}
+        Self {new: true, path}
     }
 }
 
@@ -39,22 +47,19 @@ impl<'a> Iterator for TreeIter<'a> {
         if !self.new {
             let mut me = *self.path.last().expect("Tree iterator path corrupted");
             self.path.pop().expect("Tree iterator path corrupted");
-            let parent = match self.path.last() {
-                Some(p) => p,
-                None => return None // There was no parent.
-            };
+            let parent = self.path.last()?;
             
             // Try going horizontally
             let mut take_next_child = false;
             let mut took_child = false;
             for child in &parent.children {
                 if take_next_child {
-                    self.path.push(&child);
-                    me = &child;
+                    self.path.push(child);
+                    me = child;
                     took_child = true;
                     break;
                 }
-                if **child == *me {
+                if *child == *me {
                     // Take the next child
                     take_next_child = true;
                 }
@@ -85,7 +90,7 @@ impl<'a> Iterator for TreeIter<'a> {
                 text += "/";
             }
             previous = true;
-            text = text + &n.name[..];
+            text += &n.name[..];
         }
 
         Some(text)
@@ -93,20 +98,23 @@ impl<'a> Iterator for TreeIter<'a> {
 }
 
 impl Node {
-    pub fn new() -> MyResult<Node> {
+    /// The tree of every markdown file in the text/ directory of the wiki
+    pub fn new(ctx: &Context) -> MyResult<Node> {
         let mut tree = Node { children: Vec::new(), name: ".".to_owned(), is_leaf: false};
 
-        tree.make(Root::get_path_from_local("text").expect("Could not access text directory"))?;
+        tree.make(ctx.path("text"))?;
 
         Ok(tree)
     }
 
     fn make (&mut self, node_path: String) -> MyResult<()>{
-        for new_path in match fs::read_dir(&node_path) {
-            Ok(p) => p,
-            Err(_) => return Err(format!("Could not open path {}", node_path))
-        } {
-            let new_path = new_path.expect("Could not open path").path();
+        let mut new_paths = match fs::read_dir(&node_path) {
+            Ok(p) => p.map(|p| p.expect("Could not open path").path()).collect::<Vec<_>>(),
+            Err(_) => return Err(format!("Could not open path {}", node_path).into())
+        };
+        // Sort so that pages are always compiled in the same order, whatever the filesystem
+        new_paths.sort();
+        for new_path in new_paths {
 
             if match new_path.as_path().file_name() {
                 Some(e) => e == ".git" || e == "html" || e == ".wikid",
@@ -139,34 +147,87 @@ impl Node {
         Ok(())
     }
 
-    pub fn compile(&self, file_queue: &mut FileQueue, ref_map: &RefMap, public: bool) -> MyResult<()> {
-        for path in self.iter() {
-            let html_name = if path.ends_with("_toc.md") {
-                format!("{}index.html", &path[2..path.len()-7])
-            } else {
-                format!("{}.html", &path[2..path.len() - 3])
-            };
-            let end_text = compile_file(&format!("text/{}", &path[2..]), file_queue, ref_map, public)?;
-            file_queue.add(html_name, end_text);
+    /// Compile every page into the file queue. Returns the references between pages.
+    pub fn compile(&self, file_queue: &mut FileQueue, pages: &[Page], ctx: &Context, report: &mut Report) -> MyResult<Backlinks> {
+        // Drafts are left out of public builds
+        let pages = pages.iter().filter(|p| !(ctx.public && p.draft)).cloned().collect::<Vec<_>>();
+        let mut compiled = Vec::new();
+        let mut backlinks = Backlinks::new();
+        for page in &pages {
+            let (text, references) = compile_file(&page.local_path, file_queue, &pages, ctx, report)?;
+            backlinks.add(references);
+            compiled.push((page, text));
         }
-        Ok(())
+
+        // Backlinks can only be written once every page has been compiled
+        for (page, mut text) in compiled {
+            text.push_str(&backlinks.render(page, &pages, ctx)?);
+            text.push_str(&page_end(&ctx.root)?);
+            file_queue.add(page.html_path()[5..].to_owned(), text);
+        }
+        build_tag_pages(&pages, file_queue, ctx)?;
+        build_search(&pages, file_queue, ctx)?;
+        if ctx.public {
+            build_feeds(&pages, file_queue, ctx)?;
+            build_sitemap(&pages, file_queue, ctx)?;
+        }
+        // Redirects are not listed in the sitemap
+        build_redirects(file_queue, ctx)?;
+        Ok(backlinks)
     }
 
-    pub fn ref_map(&self, public: bool) -> MyResult<RefMap> {
-        let mut ref_map = RefMap::new(public);
-        for path in self.iter() {
-            ref_map.add_file(&format!("text/{}", &path[2..]))?;
+    pub fn ref_map(&self, pages: &[Page], ctx: &Context, report: &mut Report) -> MyResult<RefMap> {
+        let mut ref_map = RefMap::new(ctx.public);
+        for page in pages {
+            if ctx.settings(&page.local_path).ignore {
+                ref_map.add_ignored(&page.local_path, ctx)?;
+                continue;
+            }
+            ref_map.add_file(&page.local_path, ctx.public && page.draft, ctx, report)?;
         }
-        ref_map.add_glossary(&Root::get_path_from_local("_glossary.md").expect("Root directory corrupted"))?;
+        ref_map.add_glossary(&ctx.path("_glossary.md"))?;
         Ok(ref_map)
     }
 
+    /// Read the title, summary and date of every page in the tree. Pages which cannot be read are
+    /// reported and left out.
+    pub fn pages(&self, ctx: &Context, report: &mut Report) -> Vec<Page> {
+        let mut pages = Vec::new();
+        for path in self.local_paths() {
+            match Page::read(&path, ctx) {
+                Ok(p) => pages.push(p),
+                Err(d) => report.add(d),
+            };
+        }
+        pages
+    }
+
+    /// Paths of every markdown file in the tree, starting with text/
+    pub fn local_paths(&self) -> Vec<String> {
+        self.iter().map(|path| format!("text/{}", &path[2..])).collect()
+    }
+
+    /// Paths of every directory in the tree, starting with text/, each before its children
+    pub fn local_dirs(&self) -> Vec<String> {
+        let mut dirs = Vec::new();
+        self.add_dirs("text", &mut dirs);
+        dirs
+    }
+
+    fn add_dirs(&self, path: &str, dirs: &mut Vec<String>) {
+        for child in self.children.iter().filter(|c| !c.is_leaf) {
+            let child_path = format!("{}/{}", path, child.name);
+            dirs.push(child_path.clone());
+            child.add_dirs(&child_path, dirs);
+        }
+    }
+
     fn iter<'a>(&'a self) -> TreeIter<'a> {
         TreeIter::new(self)
     }
 
-    fn new_node(name: String, is_leaf: bool) -> Box<Node> {
-        Box::new(Node { children: Vec::new(), name, is_leaf})
+    fn new_node(name: String, is_leaf: bool) -> Node {
+        Node { children: Vec::new(), name, is_leaf}
     }
 
     pub fn size(&self) -> usize {
@@ -0,0 +1,150 @@
+use std::path::Path;
+use crate::root::Root;
+use crate::section::Section;
+use crate::constants::{MyResult, BG_FITS};
+use crate::build::refs::RefMap;
+
+/// The settings which apply to a page or section: those of wikid.json, overridden in turn by each
+/// section containing it.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub color: String,
+    /// Theme of the pages, or None to follow the reader's system preference
+    pub dark_mode: Option<bool>,
+    pub fonts: Option<Vec<String>>,
+    pub font_size: u32,
+    /// Path of the background image from the wiki directory, and the name it is copied to in
+    /// html/css
+    pub bg_image: Option<(String, String)>,
+    /// How the background image fills the page, one of BG_FITS
+    pub bg_fit: String,
+    /// Percentage of the background color laid over the background image
+    pub bg_dim: u32,
+    /// Radius in pixels of the blur applied to the background image
+    pub bg_blur: u32,
+    /// Color drawn beneath the background image, if not the theme's background color
+    pub bg_color: Option<String>,
+    pub description: Option<String>,
+    pub index_sort: Option<String>,
+    pub theme: String,
+    /// Paths of the CSS files from the wiki directory appended to the theme, outermost first, each
+    /// with the settings file which names it
+    pub custom_css: Vec<(String, String)>,
+    /// Set if any containing section is ignored
+    pub ignore: bool,
+    /// Set if any containing section is a draft
+    pub draft: bool,
+}
+
+/// Name in html/css of the background image at `path`, keeping its extension
+fn bg_image_name(prefix: &str, path: &str) -> String {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("png").to_lowercase();
+    format!("{}background_image.{}", prefix, extension)
+}
+
+/// Everything a build needs to know about the wiki, loaded once when the build starts and shared
+/// by every page.
+pub struct Context {
+    pub root: Root,
+    pub sections: Vec<Section>,
+    /// Compile with public links
+    pub public: bool,
+    /// Set when checking rather than building. Applets are not compiled.
+    pub check: bool,
+    pub ref_map: RefMap,
+}
+
+impl Context {
+    /// Read wikid.json and the sections of the wiki found by `Root::find_dir(chosen)`. The
+    /// reference map starts empty.
+    pub fn load(chosen: Option<&str>, public: bool, check: bool) -> MyResult<Context> {
+        let root = Root::summon(chosen)?;
+        let sections = root.get_sections()?;
+        Ok(Context { root, sections, public, check, ref_map: RefMap::new(public) })
+    }
+
+    /// URL of a file given its path from the wiki directory
+    pub fn link(&self, local_path: &str) -> MyResult<String> {
+        self.root.get_link_from_local(local_path, self.public)
+    }
+
+    /// The settings of the file or directory at `local_path`, which starts with text/
+    pub fn settings(&self, local_path: &str) -> Settings {
+        let root = &self.root;
+        let mut settings = Settings {
+            color: root.main_color.clone(),
+            dark_mode: root.dark_mode,
+            fonts: root.fonts.clone(),
+            font_size: root.font_size,
+            bg_image: root.bg_image.clone().map(|b| { let name = bg_image_name("", &b); (b, name) }),
+            bg_fit: root.bg_fit.clone().unwrap_or_else(|| BG_FITS[0].to_owned()),
+            bg_dim: root.bg_dim.unwrap_or(0),
+            bg_blur: root.bg_blur.unwrap_or(0),
+            bg_color: root.bg_color.clone(),
+            description: root.description.clone(),
+            index_sort: root.index_sort.clone(),
+            theme: root.theme.clone().unwrap_or_else(|| "default".to_owned()),
+            custom_css: root.custom_css.iter().map(|c| (c.clone(), ".wikid/wikid.json".to_owned())).collect(),
+            ignore: false,
+            draft: false,
+        };
+        // Sections are listed before the sections inside them
+        for section in &self.sections {
+            if local_path != section.path && !local_path.starts_with(&format!("{}/", section.path)) {
+                continue;
+            }
+            let o = &section.overrides;
+            if !section.color.is_empty() {
+                settings.color = section.color.clone();
+            }
+            settings.ignore |= section.ignore;
+            settings.draft |= section.draft;
+            settings.dark_mode = o.dark_mode.or(settings.dark_mode);
+            settings.font_size = o.font_size.unwrap_or(settings.font_size);
+            if o.fonts.is_some() {
+                settings.fonts = o.fonts.clone();
+            }
+            if let Some(b) = &o.bg_image {
+                settings.bg_image = Some((b.clone(), bg_image_name(&format!("{}_", section.path[5..].replace('/', "_")), b)));
+            }
+            if let Some(f) = &o.bg_fit {
+                settings.bg_fit = f.clone();
+            }
+            settings.bg_dim = o.bg_dim.unwrap_or(settings.bg_dim);
+            settings.bg_blur = o.bg_blur.unwrap_or(settings.bg_blur);
+            if o.bg_color.is_some() {
+                settings.bg_color = o.bg_color.clone();
+            }
+            if o.description.is_some() {
+                settings.description = o.description.clone();
+            }
+            if o.index_sort.is_some() {
+                settings.index_sort = o.index_sort.clone();
+            }
+            if let Some(t) = &o.theme {
+                settings.theme = t.clone();
+            }
+            settings.custom_css.extend(o.custom_css.iter().map(|c| (c.clone(), format!("{}/.wikid.json", section.path))));
+        }
+        settings
+    }
+
+    /// The innermost section containing the file or directory at `local_path`
+    pub fn section(&self, local_path: &str) -> Option<&Section> {
+        self.sections.iter().rev()
+            .find(|s| local_path == s.path || local_path.starts_with(&format!("{}/", s.path)))
+    }
+
+    /// Path of the stylesheet for the file at `local_path`, starting with html/
+    pub fn css_path(&self, local_path: &str) -> String {
+        match self.section(local_path) {
+            Some(s) => format!("html/css/{}.css", &s.path[5..]),
+            None => "html/css/text.css".to_owned(),
+        }
+    }
+
+    /// Absolute path of a file given its path from the wiki directory
+    pub fn path(&self, local_path: &str) -> String {
+        self.root.path(local_path)
+    }
+}
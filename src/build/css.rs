@@ -1,172 +1,246 @@
+use std::fs;
 use std::str::FromStr;
-use crate::root::Root;
 use crate::constants::Color;
 use crate::build::file_queue::FileQueue;
+use crate::constants::{MyResult, BG_IMAGE_EXTENSIONS};
+use crate::build::context::{Context, Settings};
+use crate::diagnostics::{Diagnostic, codes};
+
+/// Replace each `$name` in `template` by its value in `vars`. `$$` is a literal dollar sign.
+fn fill_template(template: &str, vars: &[(&str, String)]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(index) = rest.find('$') {
+        out.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = r;
+            continue;
+        }
+        let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let name = &rest[..length];
+        match vars.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => out.push_str(value),
+            None => return Err(format!("Unknown variable ${}. Use one of {}", name,
+                vars.iter().map(|(n, _)| format!("${}", n)).collect::<Vec<_>>().join(", ")))
+        };
+        rest = &rest[length..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
 
-fn css_text(c: &str, public: bool) -> String {
-    let bw = Color::from_str(c).expect("Color was corrupted").bw().to_string();
-    let light = Color::from_str(c).expect("Color was corrupted").light().to_string();
-    let text = Color::from_str(c).expect("Color was corrupted").text().to_string();
-    let bg = Color::from_str(c).expect("Color was corrupted").bg().to_string();
-    let root = Root::summon().unwrap();
+/// The stylesheet for pages with `settings`, to be written to `css_path` inside html/: the
+/// palettes and fonts, then the theme, then the custom CSS files.
+fn css_text(settings: &Settings, css_path: &str, ctx: &Context) -> MyResult<String> {
+    let color = Color::from_str(&settings.color).expect("Color was corrupted");
+    let dark_palette = color.palette(true);
+    let light_palette = color.palette(false);
+    // A configured theme is used whatever the system preference, which is otherwise followed
+    let scheme = match settings.dark_mode {
+        Some(dark_mode) => format!(r":root {{
+    {}
+}}
+", color.palette(dark_mode)),
+        None => format!(r":root {{
+    {dark_palette}
+}}
+
+@media (prefers-color-scheme: light) {{
+    :root {{
+        {light_palette}
+    }}
+}}
+"),
+    };
     let mut preamble = "".to_owned();
-    let bg_image = match root.bg_image {
-        Some(_) => {
-            format!(r"background-image: url(../css/background_image.png);
-    background-repeat: no-repeat;
+    let bg_color = settings.bg_color.clone().unwrap_or_else(|| "var(--bg)".to_owned());
+    // A blurred image is drawn behind the body, so that its content is not blurred too
+    let mut backdrop = "".to_owned();
+    let background = match &settings.bg_image {
+        Some((_, name)) => {
+            // Relative to the stylesheet, which may be in a subdirectory of css/
+            let up = "../".repeat(css_path.matches('/').count());
+            let mut layers = format!("url({up}css/{name})");
+            if settings.bg_dim > 0 {
+                let wash = format!("color-mix(in srgb, var(--bg) {}%, transparent)", settings.bg_dim);
+                layers = format!("linear-gradient({wash}, {wash}), {layers}");
+            }
+            let (repeat, size) = match settings.bg_fit.as_str() {
+                "tile" => ("repeat", "auto"),
+                "cover" => ("no-repeat", "cover"),
+                "contain" => ("no-repeat", "contain"),
+                _ => ("no-repeat", "100% 100%"),
+            };
+            let image = format!(r"background-color: {bg_color};
+    background-image: {layers};
+    background-repeat: {repeat};
+    background-position: center;
     background-attachment: fixed;
-    background-size: 100% 100%;")
+    background-size: {size};");
+            match settings.bg_blur {
+                0 => image,
+                blur => {
+                    // Reach past the edges of the page, which blurring fades out
+                    backdrop = format!(r#"
+body::before {{
+    content: "";
+    position: fixed;
+    top: -{edge}px;
+    bottom: -{edge}px;
+    left: -{edge}px;
+    right: -{edge}px;
+    z-index: -1;
+    filter: blur({blur}px);
+    {image}
+}}
+"#, edge=2 * blur);
+                    format!("background: {bg_color};")
+                }
+            }
         },
         None => {
-            format!("background: {};", bg)
+            format!("background: {bg_color};")
         }
     };
-    let font_family = match &root.fonts {
-        Some(fonts) => {
-            let mut out = "".to_owned();
-            let mut font_index = 0;
-            for item in fonts {
-                if item.ends_with(".ttf") {
-                    font_index += 1;
-                    // This is a path. Make the font css entry
-                    let name = format!("font{font_index}");
-                    let item_link = root.get_link_from_local(item, public).unwrap();
-                    preamble = format!("{preamble}
+    let mut fonts = "".to_owned();
+    let mut font_index = 0;
+    for item in settings.fonts.iter().flatten() {
+        if item.ends_with(".ttf") {
+            font_index += 1;
+            // This is a path. Make the font css entry
+            let name = format!("font{font_index}");
+            let item_link = ctx.link(item)?;
+            preamble = format!("{preamble}
 @font-face {{
     font-family: \"{name}\";
     src: url(\"{item_link}\");
 }}");
-                    out = format!("{} '{}', ", out, name);
-                } else {
-                    out = format!("{} '{}', ", out, item);
-                }
-            }
-            out = format!("{} 'sans-serif'", out);
-            out
-        },
-        None => {
-            "'DM Sans', 'Nunito Sans', sans-serif".to_owned()
+            fonts = format!("{}'{}', ", fonts, name);
+        } else {
+            fonts = format!("{}'{}', ", fonts, item);
         }
+    }
+    let font_family = match &settings.fonts {
+        Some(_) => format!("{}'sans-serif'", fonts),
+        None => "'DM Sans', 'Nunito Sans', sans-serif".to_owned(),
+    };
+    let vars = [
+        ("color", settings.color.clone()),
+        ("bw", color.bw().to_string()),
+        ("text", "var(--text)".to_owned()),
+        ("bg", "var(--bg)".to_owned()),
+        ("light", "var(--light)".to_owned()),
+        ("background", background),
+        ("fonts", fonts.trim_end().to_owned()),
+        ("font_family", font_family),
+        ("font_size", format!("{}px", settings.font_size)),
+    ];
+    let theme = match fill_template(&crate::theme::template(&settings.theme, &ctx.root)?, &vars) {
+        Ok(t) => t,
+        Err(e) => return Err(format!("{} in the theme {}", e, settings.theme).into())
     };
-    let font_size = root.font_size;
+    let mut custom = String::new();
+    for (path, settings_file) in &settings.custom_css {
+        match fs::read_to_string(ctx.path(path)) {
+            Ok(t) => custom.push_str(&format!("\n/* {} */\n{}", path, t)),
+            Err(_) => return Err(missing_css(path, settings_file, ctx))
+        };
+    }
 
-    format!(
-r"
+    Ok(format!(
+r#"
 {preamble}
-body {{
-    font-size: {font_size}px;
-    font-family: {font_family};
-    color: {text};
-    {bg_image}
+{scheme}
+:root[data-theme="dark"] {{
+    {dark_palette}
 }}
 
-h1, h2, h3, h4, h5 {{
-    color: {text};
-    line-height: 1.45;
+:root[data-theme="light"] {{
+    {light_palette}
 }}
 
-a {{
-    color: {c};
-    font-weight: bold;
-    text-decoration: none;
-}}
+@media print {{
+    :root, :root[data-theme] {{
+        {light_palette}
+    }}
 
-a:visited {{
-    color: {c};
-    font-weight: normal;
+    #theme-toggle {{
+        display: none;
+    }}
 }}
+{backdrop}
+{theme}{custom}"#))
+}
 
-a:hover {{
-    background: {c};
-    color: {bw};
-}}
-
-#content {{
-    padding-top: 100px;
-    width: 50em;
-    margin: auto;
-    line-height: 150%;
-    padding-bottom: 3em;
-}}
-
-#footer {{
-    border-top: solid {text} 2px;
-    padding-top: 1em;
-    padding-bottom: 1em;
-    padding-left: 15em;
-    padding-right: 15em;
-    margin: auto;
-    width: 50em;
-}}
-
-.footnote {{
-    font-size: 14px;
-}}
-
-.collapsible {{
-    background-color: {bg};
-    color: white;
-    cursor: pointer;
-    width: 100%;
-    padding-left: 5px;
-    line-height: 100%;
-    border: none;
-    text-align: left;
-    outline: none;
-    font-family: {font_family};
-}}
-  
-.collapsible:hover {{
-    background-color: {light};
-}}
-
-.section {{
-    padding-left: 18px;
-    padding-top: 0px;
-    overflow: hidden;
-    transition: max-height 0.2s ease-out;
-}}
-
-.caption {{
-    font-size: 14px;
-    padding-top: 1em;
-    padding-bottom: 1em;
-}}
-
-.tooltip .tooltiptext {{
-    visibility: hidden;
-    width: 120px;
-    background-color: black;
-    color: #fff;
-    text-align: left;
-    border-radius: 6px;
-    padding: 15px;
-
-    /* Position the tooltip */
-    position: absolute;
-    z-index: 1;
-}}
+/// The error for a custom CSS file which could not be read, pointing to the `custom_css` setting
+/// in `settings_file` which names it
+fn missing_css(path: &str, settings_file: &str, ctx: &Context) -> Diagnostic {
+    // Section settings are named by the section's path inside text/
+    let key = match settings_file.strip_prefix("text/").and_then(|f| f.strip_suffix("/.wikid.json")) {
+        Some(section) => format!("{}.custom_css", section),
+        None => "custom_css".to_owned(),
+    };
+    let diagnostic = Diagnostic::error(format!("Could not read the custom CSS file {}", path))
+        .code(codes::MISSING_CSS)
+        .in_file(settings_file)
+        .help(format!("Create the file, or unset it with wikid config set {} \"\"", key));
+    let text = fs::read_to_string(ctx.path(settings_file)).unwrap_or_default();
+    match text.lines().enumerate().find(|(_, l)| l.contains("\"custom_css\"")) {
+        Some((i, line)) => {
+            let column = line.find("\"custom_css\"").expect("Line contains the key");
+            diagnostic.at_line(i + 1, line).at_column(line[..column].chars().count() + 1, "\"custom_css\"".len())
+        },
+        None => diagnostic,
+    }
+}
 
-.eq {{
-    display: flex;
-    flex-direction: row;
-    align-items: center;
-}}
-.eqtext {{
-    width: 95%;
-}}
+/// Write the stylesheet of every section, and of the pages outside any section, and copy their
+/// background images.
+pub fn build_css(ctx: &Context, file_queue: &mut FileQueue) -> MyResult<()> {
+    let mut stylesheets = ctx.sections.iter()
+        .filter(|sec| !ctx.settings(&sec.path).ignore)
+        .map(|sec| (format!("css/{}.css", &sec.path[5..]), ctx.settings(&sec.path)))
+        .collect::<Vec<_>>();
+    stylesheets.push(("css/text.css".to_owned(), ctx.settings("text")));
+    for (css_path, settings) in stylesheets {
+        if let Some((image, image_name)) = &settings.bg_image {
+            let extension = image.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default();
+            if !BG_IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+                return Err(format!("The background image {} must be one of {}", image, BG_IMAGE_EXTENSIONS.join(", ")).into());
+            }
+            file_queue.append_imgs(vec![(ctx.path(image), format!("css/{}", image_name))]);
+        }
+        let css = css_text(&settings, &css_path, ctx)?;
+        file_queue.add(css_path, css);
+    }
+    Ok(())
+}
 
-.eqnum {{
-    width: 5%;
-    text-align: right;
-}}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_template_replaces_variables() {
+        let vars = [("color", "#123456".to_owned()), ("font_size", "16px".to_owned())];
+        assert_eq!(fill_template("a { color: $color; font-size: $font_size }", &vars).unwrap(),
+            "a { color: #123456; font-size: 16px }");
+        assert_eq!(fill_template("$color$color", &vars).unwrap(), "#123456#123456");
+        assert_eq!(fill_template("no variables", &vars).unwrap(), "no variables");
+    }
 
-")
-}
+    #[test]
+    fn fill_template_escapes_dollars() {
+        let vars = [("color", "red".to_owned())];
+        assert_eq!(fill_template("content: \"$$5\"; $color", &vars).unwrap(), "content: \"$5\"; red");
+    }
 
-pub fn build_css(root: &Root, file_queue: &mut FileQueue, public: bool) {
-    for sec in &root.get_sections() {
-        file_queue.add(format!("css/{}.css", sec.name), css_text(&sec.color, public));
+    #[test]
+    fn fill_template_rejects_unknown_variables() {
+        let vars = [("color", "red".to_owned())];
+        assert_eq!(fill_template("$colour", &vars).unwrap_err(), "Unknown variable $colour. Use one of $color");
+        assert!(fill_template("trailing $", &vars).is_err());
     }
-    file_queue.add("css/text.css".to_owned(), css_text(&root.main_color, public));
 }
@@ -0,0 +1,146 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use crate::root::Root;
+use crate::build::context::Context;
+use crate::constants::MyResult;
+use crate::build::file_queue::FileQueue;
+use crate::build::pages::Page;
+
+/// Escape text for use inside an XML element or attribute.
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Titles and summaries are plain text, Atom's default, so that readers show them as written
+/// rather than reading them as HTML.
+fn atom_feed(title: &str, feed_path: &str, pages: &[&Page], root: &Root) -> MyResult<String> {
+    let feed_link = root.get_link_from_local(feed_path, true)?;
+    let home_link = root.get_link_from_local("html/index.html", true)?;
+    let updated = match pages.first() {
+        Some(p) => p.date,
+        None => chrono::DateTime::UNIX_EPOCH,
+    };
+
+    let mut out = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+    <title>{title}</title>
+    <id>{feed_link}</id>
+    <link rel="self" href="{feed_link}"/>
+    <link href="{home_link}"/>
+    <updated>{updated}</updated>
+    <author><name>{name}</name></author>
+"#, title=xml_escape(title), updated=updated.to_rfc3339(), name=xml_escape(&root.name));
+    for page in pages {
+        let link = root.get_link_from_local(&page.html_path(), true)?;
+        let author = match &page.front_matter.author {
+            Some(a) => format!("\n        <author><name>{}</name></author>", xml_escape(a)),
+            None => String::new(),
+        };
+        out.push_str(&format!(r#"    <entry>
+        <title>{title}</title>
+        <id>{link}</id>
+        <link href="{link}"/>
+        <updated>{updated}</updated>{author}
+        <summary>{summary}</summary>
+    </entry>
+"#, title=xml_escape(&page.title), updated=page.date.to_rfc3339(), summary=xml_escape(&page.summary)));
+    }
+    out.push_str("</feed>\n");
+    Ok(out)
+}
+
+fn rss_feed(title: &str, description: &str, pages: &[&Page], root: &Root) -> MyResult<String> {
+    let home_link = root.get_link_from_local("html/index.html", true)?;
+    let mut out = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+<channel>
+    <title>{title}</title>
+    <link>{home_link}</link>
+    <description>{description}</description>
+"#, title=xml_escape(title), description=xml_escape(description));
+    for page in pages {
+        let link = root.get_link_from_local(&page.html_path(), true)?;
+        out.push_str(&format!(r#"    <item>
+        <title>{title}</title>
+        <link>{link}</link>
+        <guid>{link}</guid>
+        <pubDate>{date}</pubDate>
+        <description>{summary}</description>
+    </item>
+"#, title=xml_escape(&page.title), date=page.date.to_rfc2822(), summary=xml_escape(&page.summary)));
+    }
+    out.push_str("</channel>\n</rss>\n");
+    Ok(out)
+}
+
+/// Write an Atom feed of every page to `html/feed.xml`, and one for each section to
+/// `html/<section>/feed.xml`. If `rss` is set in wikid.json, also write RSS 2.0 feeds to `rss.xml`.
+pub fn build_feeds(pages: &[Page], file_queue: &mut FileQueue, ctx: &Context) -> MyResult<()> {
+    let root = &ctx.root;
+    let mut pages = pages.iter().filter(|p| !p.is_toc()).collect::<Vec<_>>();
+    pages.sort_by_key(|p| Reverse(p.date));
+
+    // Section path (starting with text), pages
+    let mut feeds: BTreeMap<&str, Vec<&Page>> = BTreeMap::new();
+    feeds.insert("text", pages.clone());
+    for page in &pages {
+        if page.section_path() != "text" {
+            feeds.entry(page.section_path()).or_default().push(page);
+        }
+    }
+
+    for (section_path, feed_pages) in feeds {
+        let (dir, title) = match section_path.strip_prefix("text/") {
+            Some(s) => (format!("{}/", s), format!("{}: {}", root.name, s)),
+            None => (String::new(), root.name.clone()),
+        };
+        let feed_path = format!("{}feed.xml", dir);
+        file_queue.add(feed_path.clone(), atom_feed(&title, &format!("html/{}", feed_path), &feed_pages, root)?);
+        if root.rss {
+            let description = ctx.settings(section_path).description.unwrap_or_else(|| title.clone());
+            file_queue.add(format!("{}rss.xml", dir), rss_feed(&title, &description, &feed_pages, root)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_escape_escapes_markup() {
+        assert_eq!(xml_escape("Fish & <chips> \"now\""), "Fish &amp; &lt;chips&gt; &quot;now&quot;");
+        assert_eq!(xml_escape("&amp;"), "&amp;amp;");
+        assert_eq!(xml_escape("plain"), "plain");
+    }
+
+    #[test]
+    fn entries_escape_titles_once() {
+        let root: Root = serde_json::from_str(r##"{"wikid_version_major": "0", "wikid_version_minor": "1", "name": "Notes & more",
+            "public_url": "https://example.com", "main_color": "#cccccc", "font_size": 16}"##).unwrap();
+        let (front_matter, _) = crate::build::front_matter::FrontMatter::parse("---\ntitle: A & B <i>\n---").unwrap();
+        let page = Page {
+            local_path: "text/a.md".to_owned(),
+            title: front_matter.title.clone().unwrap(),
+            summary: "Fish & chips".to_owned(),
+            date: chrono::DateTime::UNIX_EPOCH,
+            words: 0,
+            headings: Vec::new(),
+            labels: Vec::new(),
+            text: String::new(),
+            draft: false,
+            front_matter,
+        };
+        let atom = atom_feed(&root.name, "html/feed.xml", &[&page], &root).unwrap();
+        assert!(atom.contains("<title>Notes &amp; more</title>"));
+        assert!(atom.contains("<title>A &amp; B &lt;i&gt;</title>"));
+        assert!(atom.contains("<summary>Fish &amp; chips</summary>"));
+        let rss = rss_feed(&root.name, "Notes", &[&page], &root).unwrap();
+        assert!(rss.contains("<title>A &amp; B &lt;i&gt;</title>"));
+        assert!(!rss.contains("&amp;amp;"));
+    }
+}
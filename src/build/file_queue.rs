@@ -1,16 +1,18 @@
 use std::fs::{self, create_dir, File};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use crate::constants::MyResult;
 use std::io::Write;
+use std::path::Path;
 
 pub struct FileQueue {
-    map: HashMap<String, String>,
+    map: BTreeMap<String, String>,// Ordered, so that files are always written in the same order
+    /// Absolute path of each image and the path to copy it to inside the target directory
     imgs: Vec<(String, String)>,
 }
 
 impl FileQueue {
     pub fn new() -> FileQueue {
-        FileQueue { map: HashMap::new(), imgs: Vec::new() }
+        FileQueue { map: BTreeMap::new(), imgs: Vec::new() }
     }
 
     pub fn append_imgs(&mut self, imgs: Vec<(String, String)>) {
@@ -25,7 +27,7 @@ impl FileQueue {
             name.retain(|&x| x != ".");
             for i in 1..name.len() {
                 let joined_name = name[..i].join("/");
-                if let Err(_) = create_dir(&joined_name) {
+                if create_dir(&joined_name).is_err() {
                     // Directory probably already existed
                 };
             }
@@ -35,17 +37,22 @@ impl FileQueue {
         for (key, value) in &self.map {
             let mut f = match File::create(format!("{}{}", target_dir, key)) {
                 Ok(f) => f,
-                Err(_) => return Err("Could not create all files".to_owned())
+                Err(_) => return Err("Could not create all files".into())
             };
 
-            if let Err(_) = f.write_all(value.as_bytes()) {
-                return Err("Could not write to all files".to_owned());
+            if f.write_all(value.as_bytes()).is_err() {
+                return Err("Could not write to all files".into());
             };
         }
 
         for (from, to) in self.imgs {
-            if let Err(_) = fs::copy(&from, &to) {
-                return Err(format!("Could not move image at {} to {}", from, to));
+            let to = format!("{}{}", target_dir, to);
+            // Images may be linked from another section's directory
+            if let Some(dir) = Path::new(&to).parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            if fs::copy(&from, &to).is_err() {
+                return Err(format!("Could not move image at {} to {}", from, to).into());
             }
         }
 
@@ -56,6 +63,15 @@ impl FileQueue {
         self.map.insert(name, text);
     }
 
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    /// Names of all the HTML files to be written, in alphabetical order
+    pub fn html_files(&self) -> Vec<String> {
+        self.map.keys().filter(|k| k.ends_with(".html")).cloned().collect()
+    }
+
     pub fn size(&self) -> usize {
         self.map.len()
     }
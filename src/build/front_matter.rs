@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde_json::Value;
+use crate::constants::MyResult;
+use crate::diagnostics::{Diagnostic, codes};
+use crate::build::compile::html_escape;
+
+/// Metadata given in a block at the top of a page, delimited by `---` (YAML) or `+++` (TOML).
+#[derive(Debug, Clone, Default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub date: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub summary: Option<String>,
+    pub draft: bool,
+    pub author: Option<String>,
+    /// Every field not listed above
+    pub custom: BTreeMap<String, Value>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+impl FrontMatter {
+    /// Read the front matter at the top of `text`. Returns the front matter and the number of lines
+    /// it occupied, which should be skipped when compiling the page. A first line of `---` or `+++`
+    /// which is never closed is part of the page, such as a horizontal rule.
+    pub fn parse(text: &str) -> MyResult<(FrontMatter, usize)> {
+        let lines = text.lines().collect::<Vec<_>>();
+        let (format, delimiter) = match lines.first().map(|l| l.trim_end()) {
+            Some("---") => (Format::Yaml, "---"),
+            Some("+++") => (Format::Toml, "+++"),
+            _ => return Ok((FrontMatter::default(), 0))
+        };
+        let end = match lines.iter().skip(1).position(|l| l.trim_end() == delimiter) {
+            Some(i) => i + 1,
+            None => return Ok((FrontMatter::default(), 0))
+        };
+        let block = lines[1..end].join("\n");
+
+        // The line of the page where the parser failed, if it says
+        let error = |message: String, line: Option<usize>| {
+            let diagnostic = Diagnostic::error(message).code(codes::FRONT_MATTER);
+            match line.filter(|l| *l < end) {
+                Some(l) => diagnostic.at_line(l + 1, lines[l]),
+                None => diagnostic.at_line(1, delimiter),
+            }
+        };
+        let value = match format {
+            Format::Yaml => match serde_yaml::from_str::<Value>(&block) {
+                Ok(v) => v,
+                Err(e) => return Err(error(format!("Could not read the YAML front matter ({})", e),
+                    e.location().map(|l| l.line())))
+            },
+            Format::Toml => match block.parse::<toml::Table>() {
+                Ok(t) => toml_to_json(toml::Value::Table(t)),
+                Err(e) => return Err(error(format!("Could not read the TOML front matter ({})", e.message()),
+                    e.span().map(|s| block[..s.start].matches('\n').count() + 1)))
+            },
+        };
+        let fields = match value {
+            Value::Object(o) => o.into_iter().filter(|(_, v)| !v.is_null()).collect(),
+            Value::Null => BTreeMap::new(),
+            _ => return Err(error("Front matter must be a list of key: value fields".to_owned(), None))
+        };
+
+        let front_matter = match FrontMatter::from_fields(fields) {
+            Ok(f) => f,
+            Err(d) => return Err(d.code(codes::FRONT_MATTER))
+        };
+        Ok((front_matter, end + 1))
+    }
+
+    /// HTML meta tags describing the page
+    pub fn meta_tags(&self) -> String {
+        let mut tags = Vec::new();
+        if let Some(a) = &self.author {
+            tags.push(("author".to_owned(), a.clone()));
+        }
+        if let Some(s) = &self.summary {
+            tags.push(("description".to_owned(), s.clone()));
+        }
+        if !self.tags.is_empty() {
+            tags.push(("keywords".to_owned(), self.tags.join(", ")));
+        }
+        if self.draft {
+            tags.push(("robots".to_owned(), "noindex".to_owned()));
+        }
+        for (key, value) in &self.custom {
+            tags.push((key.clone(), value_to_string(value)));
+        }
+        tags.iter()
+            .map(|(name, content)| format!("\n    <meta name=\"{}\" content=\"{}\">", html_escape(name), html_escape(content)))
+            .collect()
+    }
+
+    fn from_fields(mut fields: BTreeMap<String, Value>) -> MyResult<FrontMatter> {
+        let title = fields.remove("title").map(|v| value_to_string(&v));
+        let summary = fields.remove("summary").map(|v| value_to_string(&v));
+        let author = fields.remove("author").map(|v| value_to_string(&v));
+        let date = match fields.remove("date") {
+            Some(v) => Some(parse_date(&value_to_string(&v))?),
+            None => None,
+        };
+        let draft = match fields.remove("draft") {
+            Some(Value::Bool(b)) => b,
+            Some(v) => return Err(format!("Front matter field draft must be true or false, not {}", v).into()),
+            None => false,
+        };
+        let tags = match fields.remove("tags") {
+            Some(Value::Array(a)) => a.iter().map(value_to_string).collect(),
+            Some(v) => value_to_string(&v).split(',').map(|t| t.trim().to_owned()).filter(|t| !t.is_empty()).collect(),
+            None => Vec::new(),
+        };
+
+        Ok(FrontMatter { title, date, tags, summary, draft, author, custom: fields })
+    }
+}
+
+/// Parse a date written as `2024-01-31`, `2024-01-31 12:00:00` or in RFC 3339 format.
+pub fn parse_date(text: &str) -> MyResult<DateTime<Utc>> {
+    if let Ok(d) = DateTime::parse_from_rfc3339(text) {
+        return Ok(d.with_timezone(&Utc));
+    }
+    if let Ok(d) = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S") {
+        return Ok(d.and_utc());
+    }
+    match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        Ok(d) => Ok(d.and_hms_opt(0, 0, 0).expect("Midnight was invalid").and_utc()),
+        Err(_) => Err(format!("Could not parse date {}. Use the format YYYY-MM-DD", text).into())
+    }
+}
+
+/// The JSON form of a TOML value. Dates are written as text.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => Value::Object(t.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_without_front_matter() {
+        let (front_matter, lines) = FrontMatter::parse("# Title\nText").unwrap();
+        assert_eq!(lines, 0);
+        assert!(front_matter.title.is_none());
+    }
+
+    #[test]
+    fn parse_yaml() {
+        let text = "---\ntitle: \"Spin: a primer\"\ndate: 2024-01-31\ntags:\n  - physics\n  - quantum\ndraft: true\nlevel: 3\n---\n# Spin";
+        let (front_matter, lines) = FrontMatter::parse(text).unwrap();
+        assert_eq!(lines, 9);
+        assert_eq!(front_matter.title.as_deref(), Some("Spin: a primer"));
+        assert_eq!(front_matter.date.unwrap().to_rfc3339(), "2024-01-31T00:00:00+00:00");
+        assert_eq!(front_matter.tags, vec!["physics", "quantum"]);
+        assert!(front_matter.draft);
+        assert_eq!(front_matter.custom.get("level"), Some(&Value::from(3)));
+    }
+
+    #[test]
+    fn parse_toml() {
+        let text = "+++\ntitle = 'Atoms'\ntags = [\"physics\", \"chemistry\"]\nauthor = \"Ada\"\n+++\n";
+        let (front_matter, lines) = FrontMatter::parse(text).unwrap();
+        assert_eq!(lines, 5);
+        assert_eq!(front_matter.title.as_deref(), Some("Atoms"));
+        assert_eq!(front_matter.tags, vec!["physics", "chemistry"]);
+        assert_eq!(front_matter.author.as_deref(), Some("Ada"));
+    }
+
+    #[test]
+    fn parse_nested_values_and_comments() {
+        let text = "---\ntitle: Spin # shown in links\nsummary: |\n  Two\n  lines\nextra:\n  level: 3\n---";
+        let (front_matter, lines) = FrontMatter::parse(text).unwrap();
+        assert_eq!(lines, 8);
+        assert_eq!(front_matter.title.as_deref(), Some("Spin"));
+        assert_eq!(front_matter.summary.as_deref(), Some("Two\nlines\n"));
+        assert_eq!(front_matter.custom.get("extra"), Some(&serde_json::json!({"level": 3})));
+
+        let text = "+++\ntitle = \"Atoms\" # comment\ndate = 2024-01-31\n[extra]\nlevel = 3\n+++";
+        let (front_matter, _) = FrontMatter::parse(text).unwrap();
+        assert_eq!(front_matter.title.as_deref(), Some("Atoms"));
+        assert_eq!(front_matter.date.unwrap().to_rfc3339(), "2024-01-31T00:00:00+00:00");
+        assert_eq!(front_matter.custom.get("extra"), Some(&serde_json::json!({"level": 3})));
+    }
+
+    #[test]
+    fn unclosed_delimiter_is_not_front_matter() {
+        let (front_matter, lines) = FrontMatter::parse("---\ntitle: Unclosed\n").unwrap();
+        assert_eq!(lines, 0);
+        assert!(front_matter.title.is_none());
+    }
+
+    #[test]
+    fn parse_comma_separated_tags() {
+        let (front_matter, _) = FrontMatter::parse("---\ntags: a, b,, c\n---").unwrap();
+        assert_eq!(front_matter.tags, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(FrontMatter::parse("---\n- orphan item\n---").is_err());
+        assert!(FrontMatter::parse("---\nno separator\n---").is_err());
+        assert!(FrontMatter::parse("---\ndraft: maybe\n---").is_err());
+        assert!(FrontMatter::parse("---\ndate: 31/01/2024\n---").is_err());
+        assert!(FrontMatter::parse("+++\ntitle = unquoted\n+++").is_err());
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(parse_date("2024-01-31 12:30:00").unwrap().to_rfc3339(), "2024-01-31T12:30:00+00:00");
+        assert_eq!(parse_date("2024-01-31T12:30:00+02:00").unwrap().to_rfc3339(), "2024-01-31T10:30:00+00:00");
+    }
+
+    #[test]
+    fn meta_tags_are_escaped() {
+        let (front_matter, _) = FrontMatter::parse("---\nauthor: A \"quoted\" <name>\n---").unwrap();
+        assert_eq!(front_matter.meta_tags(), "\n    <meta name=\"author\" content=\"A &quot;quoted&quot; &lt;name&gt;\">");
+    }
+}
@@ -0,0 +1,133 @@
+use std::cmp::Reverse;
+use std::fs;
+use crate::build::context::Context;
+use crate::constants::MyResult;
+use crate::build::pages::Page;
+use crate::build::front_matter::FrontMatter;
+use crate::build::compile::html_escape;
+
+/// Keys by which an index page can be sorted.
+#[derive(Copy, Clone, Debug)]
+pub enum SortKey {
+    /// Alphabetical by title
+    Title,
+    /// Newest first
+    Date,
+    /// Shortest first
+    Length,
+    /// Alphabetical by file name
+    Path,
+}
+
+impl SortKey {
+    /// Parse a sort key such as `date` or `-title`. A leading minus reverses the order.
+    pub fn parse(text: &str) -> MyResult<(SortKey, bool)> {
+        let text = text.trim();
+        let (text, reverse) = match text.strip_prefix('-') {
+            Some(t) => (t, true),
+            None => (text, false),
+        };
+        let key = match text {
+            "title" => SortKey::Title,
+            "date" => SortKey::Date,
+            "length" => SortKey::Length,
+            "path" => SortKey::Path,
+            _ => return Err(format!("Unknown index sort key {}. Use title, date, length or path.", text).into())
+        };
+        Ok((key, reverse))
+    }
+
+    fn sort(&self, pages: &mut Vec<&Page>) {
+        match self {
+            SortKey::Title => pages.sort_by_key(|p| p.title.to_lowercase()),
+            SortKey::Date => pages.sort_by_key(|p| Reverse(p.date)),
+            SortKey::Length => pages.sort_by_key(|p| p.words),
+            SortKey::Path => pages.sort_by(|a, b| a.local_path.cmp(&b.local_path)),
+        }
+    }
+}
+
+/// If the line of a table of contents asks for a generated index, returns the requested sort key.
+/// The directive is a comment, `%index`, optionally followed by a sort key.
+pub fn index_directive(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("%index")?;
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+/// Returns true if the table of contents at `toc_path` lists its section's pages, because it is
+/// empty or contains an index directive.
+pub fn has_generated_index(toc_path: &str, ctx: &Context) -> MyResult<bool> {
+    let text = match fs::read_to_string(ctx.path(toc_path)) {
+        Ok(t) => t,
+        Err(_) => return Err(format!("Could not read table of contents {}", toc_path).into())
+    };
+    let (_, front_lines) = FrontMatter::parse(&text)?;
+    let mut lines = text.lines().skip(front_lines);
+    Ok(lines.clone().all(|l| l.trim().is_empty()) || lines.any(|l| index_directive(l).is_some()))
+}
+
+/// Make the HTML listing every page in the section whose table of contents is at `toc_path`.
+pub fn section_index(toc_path: &str, pages: &[Page], sort: &str, ctx: &Context) -> MyResult<String> {
+    let section_path = &toc_path[..toc_path.len() - "/_toc.md".len()];
+    let index_sort = ctx.settings(section_path).index_sort;
+    let sort = if sort.is_empty() {
+        index_sort.as_deref().unwrap_or("title")
+    } else {
+        sort
+    };
+    let (key, reverse) = SortKey::parse(sort)?;
+
+    let mut section_pages = pages.iter()
+        .filter(|p| !p.is_toc() && p.section_path() == section_path)
+        .collect::<Vec<_>>();
+    key.sort(&mut section_pages);
+    if reverse {
+        section_pages.reverse();
+    }
+
+    index_entries(&section_pages, ctx)
+}
+
+/// Make the HTML listing the given pages with their titles, dates, and summaries.
+pub fn index_entries(pages: &[&Page], ctx: &Context) -> MyResult<String> {
+    let mut out = "<div class=\"index\">\n".to_owned();
+    for page in pages {
+        let link = ctx.link(&page.html_path())?;
+        let author = match &page.front_matter.author {
+            Some(a) => format!("{} &middot; ", html_escape(a)),
+            None => String::new(),
+        };
+        out.push_str(&format!("<div class=\"index-entry\"><h3><a href=\"{link}\">{title}</a></h3>\
+            <div class=\"index-meta\">{date} &middot; {author}{time} min read</div><p>{summary}</p></div>\n",
+            title=html_escape(&page.title), date=page.date.format("%B %-d, %Y"), time=page.reading_time(), summary=html_escape(&page.summary)));
+    }
+    out.push_str("</div>");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_key_parse() {
+        assert!(matches!(SortKey::parse("title"), Ok((SortKey::Title, false))));
+        assert!(matches!(SortKey::parse(" -date "), Ok((SortKey::Date, true))));
+        assert!(matches!(SortKey::parse("length"), Ok((SortKey::Length, false))));
+        assert!(matches!(SortKey::parse("-path"), Ok((SortKey::Path, true))));
+        assert!(SortKey::parse("size").is_err());
+        assert!(SortKey::parse("--title").is_err());
+    }
+
+    #[test]
+    fn index_directive_needs_a_space() {
+        assert_eq!(index_directive("%index"), Some(""));
+        assert_eq!(index_directive("%index -title"), Some("-title"));
+        assert_eq!(index_directive("%indexes"), None);
+        assert_eq!(index_directive("* [a]{b}"), None);
+    }
+}
@@ -1,8 +1,8 @@
 use std::{io::{self, Write}, path::Path};
 
-use crate::root::Root;
 
-use super::{compile::ParseState, refs::RefMap, MyResult};
+use super::{compile::{ParseState, html_escape}, refs::RefMap, backlinks::Reference, MyResult};
+use crate::diagnostics::{Diagnostic, codes};
 
 /// A struct for parsing links
 #[derive(Debug)]
@@ -11,7 +11,9 @@ pub struct PossibleLink {
     link_text: String,
     link_type: char, // either [ or {
     modifiers: Modifiers,
-    progress: u8 // zero for no link, 1 for first part, 2 for intermediate, 3 for second part.
+    progress: u8, // zero for no link, 1 for first part, 2 for intermediate, 3 for second part.
+    start: usize, // columns of the opening bracket and the closing character
+    end: usize,
 }
 
 /// An enum for keeping track of the state of a link
@@ -32,7 +34,7 @@ pub enum LinkReturn {
 
 #[derive(Debug)]
 pub struct Modifiers {
-    modifiers: Vec<char>,
+    modifiers: Vec<(char, usize)>,// modifier, column it was opened at
 }
 
 impl Modifiers {
@@ -40,10 +42,11 @@ impl Modifiers {
         Modifiers {modifiers: Vec::new()}
     }
 
-    pub fn check(&mut self, c: char) -> MyResult<Option<String>> {
+    pub fn check(&mut self, c: char, column: usize) -> MyResult<Option<String>> {
         if c == '*' || c == '_' || c == '`' || c == '$' || c == ']' {
-            if self.modifiers.contains(&c) {
-                if *self.modifiers.last().unwrap() == c {
+            if let Some(&(_, opened)) = self.modifiers.iter().find(|(m, _)| *m == c) {
+                let (last, last_opened) = *self.modifiers.last().unwrap();
+                if last == c {
                     self.modifiers.pop();
                     return Ok(Some(match c {
                         '*' => "</b>".to_owned(),
@@ -51,28 +54,112 @@ impl Modifiers {
                         '`' => "</code>".to_owned(),
                         '$' => "\\)".to_owned(),
                         ']' => "</div>".to_owned(),
-                        _ => return Err(format!("Unknown modifier {}",c))
+                        _ => return Err(format!("Unknown modifier {}",c).into())
                     }));
                 }
                 else {
-                    return Err("Modifier misformatting".to_owned());
+                    return Err(Diagnostic::error(format!("Modifier {} was closed before {}", c, last))
+                        .code(codes::MODIFIER_ORDER)
+                        .at_column(opened, column + 1 - opened)
+                        .help(format!("Close the {} opened at column {} first", last, last_opened)));
                 }
             }
-            self.modifiers.push(c);
+            self.modifiers.push((c, column));
             return Ok(Some(match c {
                 '*' => "<b>".to_owned(),
                 '_' => "<i>".to_owned(),
                 '`' => "<code>".to_owned(),
                 '$' => "\\(".to_owned(),
                 '[' => "<div>".to_owned(),
-                _ => return Err(format!("Unknown modifier {}",c))
+                _ => return Err(format!("Unknown modifier {}",c).into())
             }));
         }
         Ok(None)
     }
 
     pub fn is_latex(&self) -> bool {
-        self.modifiers.contains(&'$')
+        self.modifiers.iter().any(|(m, _)| *m == '$')
+    }
+
+    /// The most recent modifier which has been opened but not closed, and its column
+    pub fn unclosed(&self) -> Option<(char, usize)> {
+        self.modifiers.last().copied()
+    }
+}
+
+/// Returns false for external links which are certainly broken, such as those containing spaces or
+/// missing a scheme
+fn is_valid_url(url: &str) -> bool {
+    if url.is_empty() || url.contains(char::is_whitespace) || url.starts_with("www.") {
+        return false;
+    }
+    match url.find(':') {
+        // Relative links and anchors
+        None => true,
+        Some(i) => {
+            let scheme = &url[..i];
+            let rest = &url[i+1..];
+            if scheme.contains('/') || scheme.contains('#') || scheme.contains('?') {
+                // The colon is not part of a scheme
+                return true;
+            }
+            if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') {
+                return false;
+            }
+            match scheme {
+                "http" | "https" | "ftp" | "file" => rest.starts_with("//") && rest.len() > 2,
+                _ => !rest.is_empty(),
+            }
+        }
+    }
+}
+
+/// The target of a `{}` link in the source of a page
+#[derive(Debug)]
+pub struct LinkTarget {
+    /// Line number, starting from 1
+    pub line: usize,
+    /// Byte offset of the target within the line
+    pub start: usize,
+    pub target: String,
+    /// Set for the paths of images, which are not labels
+    pub image: bool,
+}
+
+/// Find the target of every `{}` link in `text`, skipping applets and comments.
+pub fn find_link_targets(text: &str) -> Vec<LinkTarget> {
+    let mut targets = Vec::new();
+    for (line_num, line) in text.lines().enumerate() {
+        if line.starts_with('?') || line.starts_with('%') {
+            continue;
+        }
+        let mut rest = 0;
+        while let Some(i) = line[rest..].find("]{") {
+            let start = rest + i + 2;
+            let end = match line[start..].find('}') {
+                Some(j) => start + j,
+                None => break,
+            };
+            targets.push(LinkTarget {
+                line: line_num + 1,
+                start,
+                target: line[start..end].to_owned(),
+                image: line.starts_with('!'),
+            });
+            rest = end;
+        }
+    }
+    targets
+}
+
+/// The labels which a link target in the page at `source` may refer to, most specific first.
+/// Targets are looked up relative to the page's section before the whole wiki.
+pub fn target_keys(target: &str, source: &str) -> Vec<String> {
+    let parent = Path::new(source).parent().and_then(|p| p.to_str()).unwrap_or("");
+    if parent.len() <= 4 {
+        vec![target.to_owned()]
+    } else {
+        vec![format!("{}/{}", &parent[5..], target), target.to_owned()]
     }
 }
 
@@ -84,85 +171,82 @@ impl PossibleLink {
             link_type: '.',
             modifiers: Modifiers::new(),
             progress: 0,
+            start: 0,
+            end: 0,
         }
     }
 
-    /// Tries to add a character. If fails, returns the text to be written. Otherwise returns None.
-    pub fn try_add(&mut self, c: char) -> LinkReturn {
+    /// Tries to add a character at `column` of the line. If fails, returns the text to be written.
+    /// Otherwise returns None. Modifiers in the display text which are closed in the wrong order
+    /// are returned as an error, and the character is not added.
+    pub fn try_add(&mut self, c: char, column: usize) -> MyResult<LinkReturn> {
         match c {
             '[' => {
                 if self.progress == 0 {
                     // Begin the link
                     self.display_text = "".to_owned();
                     self.progress = 1;
-                    return LinkReturn::Pushed;
+                    self.start = column;
+                    return Ok(LinkReturn::Pushed);
                 }
                 else {
                     // Brackets are always the first item in a link
-                    return LinkReturn::Child;
+                    return Ok(LinkReturn::Child);
                 }
             },
 
-            '(' => {
-                if self.progress == 2 {
-                    self.link_type = c;
-                    self.progress = 3;
-                    return LinkReturn::Pushed;
-                }
+            '(' if self.progress == 2 => {
+                self.link_type = c;
+                self.progress = 3;
+                return Ok(LinkReturn::Pushed);
             },
 
-            '{' => {
-                if self.progress == 2 {
-                    self.link_type = c;
-                    self.progress = 3;
-                    return LinkReturn::Pushed;
-                }
+            '{' if self.progress == 2 => {
+                self.link_type = c;
+                self.progress = 3;
+                return Ok(LinkReturn::Pushed);
             },
 
 
-            ']' => {
-                if self.progress == 1 {
-                    // End the bracket
-                    self.progress = 2;
-                    return LinkReturn::Pushed;
-                }
+            ']' if self.progress == 1 => {
+                // End the bracket
+                self.progress = 2;
+                return Ok(LinkReturn::Pushed);
             },
 
-            ')' => {
-                if self.progress == 3 && self.link_type == '(' {
-                    return LinkReturn::Done;
-                }
+            ')' if self.progress == 3 && self.link_type == '(' => {
+                self.end = column;
+                return Ok(LinkReturn::Done);
             },
 
-            '}' => {
-                if self.progress == 3 && self.link_type == '{' {
-                    self.progress = 2;
-                    return LinkReturn::Done;
-                }
+            '}' if self.progress == 3 && self.link_type == '{' => {
+                self.progress = 2;
+                self.end = column;
+                return Ok(LinkReturn::Done);
             },
 
             _ => (),
         }
         match self.progress {
-            0 => return LinkReturn::Pass,
+            0 => return Ok(LinkReturn::Pass),
             1 => {
                 // Perform modifier check
-                match self.modifiers.check(c).unwrap() {
+                match self.modifiers.check(c, column)? {
                     Some(s) => self.display_text.push_str(&s),
                     None => self.display_text.push(c)
                 }
             },
-            2 => return self.prep_for_footnote(c),
+            2 => return Ok(self.prep_for_footnote(c)),
             3 => self.link_text.push(c),
             _ => panic!("Progress should not get this high")
         };
-        LinkReturn::Pushed
+        Ok(LinkReturn::Pushed)
     }
 
     pub fn prep_for_footnote(&mut self, c: char) -> LinkReturn {
         let s = self.clear(c);
         if s.len() >= 2 {
-            LinkReturn::Footnote((&s[1..(s.len()-1)]).to_owned())
+            LinkReturn::Footnote(s[1..(s.len()-1)].to_owned())
         } else {
             LinkReturn::Failed(s)
         }
@@ -176,6 +260,11 @@ impl PossibleLink {
         }
     }
 
+    /// The column and length of the link, once it has been concluded
+    pub fn span(&self) -> (usize, usize) {
+        (self.start, self.end + 1 - self.start)
+    }
+
     /// Reset the link and return the current string
     fn clear(&mut self, c: char) -> String {
         let mut out = match self.display_text.is_empty() {
@@ -195,23 +284,53 @@ impl PossibleLink {
         out
     }
 
-    pub fn make(&mut self, ref_map: &RefMap, local_path: Option<&str>) -> MyResult<String> {
+    pub fn make(&mut self, ref_map: &RefMap, parse_state: &mut ParseState, local_path: Option<&str>) -> MyResult<String> {
         // Guaranteed that self.progress is 3
         let (display_text, href) = match self.link_type {
-            '(' => (self.display_text.clone(), self.link_text.clone()),
+            '(' => {
+                if !is_valid_url(&self.link_text) {
+                    let (column, length) = self.span();
+                    parse_state.diagnostics.push(Diagnostic::warning(format!("Malformed link {}", self.link_text))
+                        .code(codes::MALFORMED_URL)
+                        .at_column(column, length)
+                        .help("External links need a scheme, such as https://"));
+                }
+                (self.display_text.clone(), Some(self.link_text.clone()))
+            },
+            '{' if ref_map.is_ignored(&self.link_text, local_path) => {
+                // Links into ignored sections are left as plain text
+                match self.display_text.is_empty() {
+                    true => (html_escape(&self.link_text), None),
+                    false => (self.display_text.clone(), None),
+                }
+            },
             '{' => {
                 // Internal link
                 let (internal_name, internal_link) = match ref_map.get_link(&self.link_text, local_path) {
                     Some(i) => i,
-                    None => return Err(format!("Could not find link {}", self.link_text))
+                    None => return Err(if ref_map.is_draft(&self.link_text, local_path) {
+                        Diagnostic::error(format!("Link {} points to a draft, which is not included in public builds", self.link_text))
+                            .code(codes::DRAFT_LINK)
+                            .help("Publish the page by removing draft from its front matter or section")
+                    } else {
+                        Diagnostic::error(format!("Could not find link {}", self.link_text))
+                            .code(codes::UNRESOLVED_LINK)
+                            .help("Link to a page by its path without .md, or to a label defined with ~")
+                    })
                 };
+                parse_state.references.push(Reference {
+                    source: parse_state.source.clone(),
+                    heading: parse_state.heading.clone(),
+                    href: internal_link.clone(),
+                    label: internal_name.clone(),
+                });
                 if self.display_text.is_empty() {
-                    (internal_name, internal_link)
+                    (html_escape(&internal_name), Some(internal_link))
                 } else {
-                    (self.display_text.clone(), internal_link)
+                    (self.display_text.clone(), Some(internal_link))
                 }
             }
-            _ => return Err("Internal link parsing error".to_owned())
+            _ => return Err("Internal link parsing error".into())
         };
 
         
@@ -221,29 +340,37 @@ impl PossibleLink {
         self.link_text = "".to_owned();
         self.link_type = '.';
 
-        Ok(format!("<a href={}>{}</a>", href, display_text))
+        Ok(match href {
+            Some(href) => format!("<a href={}>{}</a>", href, display_text),
+            None => display_text,
+        })
     }
 
     pub fn make_img(&mut self, parse_state: &mut ParseState, public: bool) -> MyResult<String> {
-        let root = Root::summon()?;
         let link_parts = self.link_text.split('?').collect::<Vec<&str>>();
         let (image_path, image_width) = if link_parts.len() == 1 {
             (link_parts[0], 100)
         } else if link_parts.len() == 2 {
             (link_parts[0], match link_parts[1].parse::<i32>() {
                 Ok(i) => i,
-                Err(_) => return Err(format!("Argument {} was not an integer", link_parts[1]))
+                Err(_) => return Err(Diagnostic::error(format!("Argument {} was not an integer", link_parts[1])).code(codes::BAD_ARGUMENT))
             })
         } else {
-            return Err(format!("Image had too many question marks in it. {}", self.link_text));
+            return Err(Diagnostic::error(format!("Image had too many question marks in it. {}", self.link_text))
+                .code(codes::BAD_ARGUMENT)
+                .help("Images take one argument, the width in percent, such as ![Caption]{image.png?50}"));
         };
         // Guaranteed that self.progress is 3
         let href = match self.link_type {
             '[' => image_path.to_owned(),
-            '{' => {parse_state.move_img(image_path)?;
-                root.get_link_from_local(&format!("html/{}/{}", &parse_state.local_path[5..], image_path), public)?
-                }, // Internal link
-            _ => return Err("Internal link parsing error".to_owned())
+            '{' => match &parse_state.asset_url {
+                Some(url) => format!("{}/{}", url.trim_end_matches('/'), image_path),
+                None => {
+                    parse_state.move_img(image_path)?;
+                    parse_state.context()?.root.get_link_from_local(&parse_state.img_html_path(image_path), public)?
+                }
+            }, // Internal link
+            _ => return Err("Internal link parsing error".into())
         };
         
         // Reset the link
@@ -259,31 +386,48 @@ impl PossibleLink {
         res
     }
     
-    pub fn make_applet(&mut self, public: bool) -> MyResult<String> {
-        let root = Root::summon()?;
+    pub fn make_applet(&mut self, parse_state: &mut ParseState, public: bool) -> MyResult<String> {
+        if parse_state.asset_url.is_some() {
+            return Err(Diagnostic::error("Applets can only be used inside a wiki").code(codes::MISSING_APPLET));
+        }
+        let ctx = parse_state.context()?;
+        let root = &ctx.root;
         let link_parts = self.link_text.split('?').collect::<Vec<&str>>();
         let (applet_path, applet_width, applet_height) = if link_parts.len() == 1 {
             (link_parts[0], 640, 480)
         } else if link_parts.len() == 3 {
             (link_parts[0], match link_parts[1].parse::<i32>() {
                 Ok(i) => i,
-                Err(_) => return Err(format!("Argument {} was not an integer", link_parts[1]))
+                Err(_) => return Err(Diagnostic::error(format!("Argument {} was not an integer", link_parts[1])).code(codes::BAD_ARGUMENT))
             }, match link_parts[2].parse::<i32>() {
                 Ok(i) => i,
-                Err(_) => return Err(format!("Argument {} was not an integer", link_parts[2]))
+                Err(_) => return Err(Diagnostic::error(format!("Argument {} was not an integer", link_parts[2])).code(codes::BAD_ARGUMENT))
             })
         } else {
-            return Err(format!("Applet had too many question marks in it. {}", self.link_text));
+            return Err(Diagnostic::error(format!("Applet had too many question marks in it. {}", self.link_text))
+                .code(codes::BAD_ARGUMENT)
+                .help("Applets take two arguments, the width and height, such as ?[Caption]{applet?640?480}"));
         };
 
         // Process the link
         let rust_path = match self.link_type {
             '[' => applet_path.to_owned(),
-            '{' => Root::get_path_from_local(&format!("code/{}", applet_path)).unwrap(),//Internal link
-            _ => return Err("Internal link parsing error".to_owned())
+            '{' => ctx.path(&format!("code/{}", applet_path)),//Internal link
+            _ => return Err("Internal link parsing error".into())
         };
         let rust_path = Path::new(&rust_path);
         let rust_path_str = rust_path.to_str().unwrap();
+        if parse_state.check {
+            // Do not create or compile applets while checking
+            if self.link_type == '{' && !rust_path.exists() {
+                return Err(Diagnostic::error(format!("Could not find applet {}", rust_path_str)).code(codes::MISSING_APPLET));
+            }
+            self.progress = 0;
+            self.link_text = "".to_owned();
+            self.link_type = '.';
+            self.display_text = "".to_owned();
+            return Ok(String::new());
+        }
         let applet_name = rust_path.file_name().unwrap().to_str().unwrap();
         let mut applet_camel_name = "".to_owned();
         let mut next_capital = true;
@@ -306,7 +450,7 @@ impl PossibleLink {
             let _=io::stdout().flush();
             io::stdin().read_line(&mut response).expect("Did not enter a correct string");
             if response == "N\n" || response == "n\n" {
-                return Err(format!("The path {} does not exist.", rust_path_str));
+                return Err(format!("The path {} does not exist.", rust_path_str).into());
             }
 
             let color = root.main_color.clone();
@@ -414,27 +558,27 @@ impl {applet_camel_name} {{
         let output = std::process::Command::new("sh").arg("-c").arg(command)
             .output();
         if let Err(e) = output {
-            return Err(format!("Could not run compilation commands for applet `{}`\n{}", applet_path, e));
+            return Err(format!("Could not run compilation commands for applet `{}`\n{}", applet_path, e).into());
         }
         let output = output.unwrap();
         if !output.status.success() {
-            return Err(format!("Compiler error while building applet `{}`\n{}", applet_path, String::from_utf8(output.stderr).unwrap()));
+            return Err(format!("Compiler error while building applet `{}`\n{}", applet_path, String::from_utf8(output.stderr).unwrap()).into());
         }
 
         // Move to bin
-        let bin_dir = Root::get_path_from_local(&format!("bin")).unwrap();
+        let bin_dir = ctx.path("bin");
         let _ = std::fs::create_dir(&bin_dir);
         
         let bin_path = format!("{}/{}_bg.wasm", bin_dir, applet_name);
         let pkg_path = format!("{}/pkg/{}_bg.wasm", rust_path.to_str().unwrap(), applet_name);
-        if let Err(_) = std::fs::rename(&pkg_path, &bin_path) {
-            return Err("Could not move the compiled file to the binary directory".to_owned());
+        if std::fs::rename(&pkg_path, &bin_path).is_err() {
+            return Err("Could not move the compiled file to the binary directory".into());
         }
 
         let bin_path = format!("{}/{}.js", bin_dir, applet_name);
         let pkg_path = format!("{}/pkg/{}.js", rust_path.to_str().unwrap(), applet_name);
-        if let Err(_) = std::fs::rename(&pkg_path, &bin_path) {
-            return Err("Could not move the compiled file to the binary directory".to_owned());
+        if std::fs::rename(&pkg_path, &bin_path).is_err() {
+            return Err("Could not move the compiled file to the binary directory".into());
         }
 
         
@@ -488,3 +632,28 @@ impl {applet_camel_name} {{
         self.display_text.push(c)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_urls() {
+        assert!(is_valid_url("https://example.com/page?q=1#top"));
+        assert!(is_valid_url("mailto:someone@example.com"));
+        assert!(is_valid_url("../other.html"));
+        assert!(is_valid_url("#section"));
+        assert!(is_valid_url("path/with:colon"));
+    }
+
+    #[test]
+    fn invalid_urls() {
+        assert!(!is_valid_url(""));
+        assert!(!is_valid_url("https://example.com/a page"));
+        assert!(!is_valid_url("www.example.com"));
+        assert!(!is_valid_url("https:example.com"));
+        assert!(!is_valid_url("http://"));
+        assert!(!is_valid_url("bad_scheme:thing"));
+        assert!(!is_valid_url("mailto:"));
+    }
+}
@@ -1,8 +1,6 @@
 use crate::constants::*;
-use crate::root::Root;
 use std::fs::{remove_dir_all, create_dir};
 use crate::build::file_queue::FileQueue;
-use crate::build::compile_tree::Node;
 
 mod css;
 mod refs;
@@ -10,12 +8,34 @@ mod file_queue;
 mod compile_tree;
 mod compile;
 mod links;
+mod pages;
+mod front_matter;
+mod index;
+mod tags;
+mod feed;
+mod sitemap;
+mod redirects;
+mod search;
+mod backlinks;
+mod report;
+mod check;
+mod context;
+mod render;
 
 use clap::Parser;
 use css::build_css;
+pub(crate) use context::Context;
+pub use check::CheckSettings;
+pub use refs::{RefMap, Label, LabelKind};
+pub(crate) use index::SortKey;
+pub(crate) use compile_tree::Node;
+pub(crate) use pages::{Page, html_path};
+pub(crate) use report::Report;
+pub(crate) use links::{find_link_targets, target_keys};
+pub use render::{RenderSettings, Rendered, render_str, render_file};
 
 #[derive(Parser)]
-pub(crate) struct BuildSettings {
+pub struct BuildSettings {
     #[arg(short, long)]
     /// Compile with public links
     public: bool,
@@ -25,60 +45,71 @@ pub(crate) struct BuildSettings {
     /// Verbosity
     #[arg(long, short)]
     verbose: bool,
+    /// Directory to write the site to instead of html/, ending with /
+    #[arg(skip)]
+    out_dir: Option<String>,
+    /// Address local links point to instead of the files
+    #[arg(skip)]
+    local_url: Option<String>,
 }
 
 impl BuildSettings {
-    pub fn run(&self) -> MyResult<()> {
-        unsafe {
-            crate::VERBOSE = self.verbose;
-        }
-        let root = Root::summon()?;
+    /// Settings for a local build into `out_dir` whose links point to `local_url`, which is not
+    /// opened afterwards
+    pub(crate) fn preview(verbose: bool, out_dir: String, local_url: String) -> BuildSettings {
+        BuildSettings { public: false, run: false, verbose, out_dir: Some(out_dir), local_url: Some(local_url) }
+    }
+
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let mut ctx = Context::load(chosen, self.public, false)?;
+        ctx.root.local_url = self.local_url.clone();
         
         if self.public {
             println!("Public build");
-            if root.public_url == "" {
-                return Err("You must first set the public url".to_owned());
+            if ctx.root.public_url.is_empty() {
+                return Err("You must first set the public url".into());
             }
         }
         
         let mut file_queue = FileQueue::new();
 
-        if let Some(image) = &root.bg_image {
-            if !image.ends_with(".png") {
-                return Err(format!("The background image {} is not a PNG", image));
-            }
-            //TODO do this
-            file_queue.append_imgs(vec![(image.to_owned(), "html/css/background_image.png".to_owned())]);
-        }
-        
         // Make css files
         if crate::is_verbose() {
             println!("Building css files");
         }
-        build_css(&root, &mut file_queue, self.public);
+        build_css(&ctx, &mut file_queue)?;
         
         // Compile
-        let compile_tree = Node::new()?;
+        let compile_tree = Node::new(&ctx)?;
         if crate::is_verbose() {
             println!("Compiling {} files", compile_tree.size());
         }
-        let ref_map = compile_tree.ref_map(self.public)?;
+        let mut report = Report::new();
+        let pages = compile_tree.pages(&ctx, &mut report);
+        ctx.ref_map = compile_tree.ref_map(&pages, &ctx, &mut report)?;
+        // Ignored sections were only read for their labels
+        let pages = pages.into_iter().filter(|p| !ctx.settings(&p.local_path).ignore).collect::<Vec<_>>();
         
-        compile_tree.compile(&mut file_queue, &ref_map, self.public)?;
+        compile_tree.compile(&mut file_queue, &pages, &ctx, &mut report)?;
+        report.print();
+        if report.errors() > 0 {
+            return Err(format!("Could not build the wiki due to {} errors", report.errors()).into());
+        }
         
         
         // Write
         let mut target_existed = true;
-        let target_dir = Root::get_path_from_local("html/")?;
-        if let Err(_) = remove_dir_all(&target_dir) {
+        let target_dir = self.out_dir.clone().unwrap_or_else(|| ctx.path("html/"));
+        if remove_dir_all(&target_dir).is_err() {
             target_existed = false;
         }
-        if let Err(_) = create_dir(&target_dir) {
+        if create_dir(&target_dir).is_err() {
             if !target_existed {
-                return Err("Could not create target directory".to_owned());
+                return Err("Could not create target directory".into());
             }
             else {
-                return Err("Could not clean target directory".to_owned());
+                return Err("Could not clean target directory".into());
             }
         }
         if crate::is_verbose() {
@@ -91,10 +122,7 @@ impl BuildSettings {
         }
         
         if self.run {
-            let path = root.get_link_from_local("html/index.html", self.public)?;
-            if let Err(_) = open::that(&path) {
-                return Err(format!("Could not display website. Link searched was {}", path));
-            }
+            crate::open::open_link(&ctx.link("html/index.html")?, &ctx.root)?;
         }
         
         Ok(())
@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use crate::constants::MyResult;
+use crate::build::front_matter::FrontMatter;
+use crate::build::context::Context;
+
+const WORDS_PER_MINUTE: usize = 200;
+const SUMMARY_LENGTH: usize = 240;
+
+/// Information about a single page, used to build index pages.
+#[derive(Debug, Clone)]
+pub struct Page {
+    /// Path to the markdown file, starting with text/
+    pub local_path: String,
+    pub title: String,
+    pub summary: String,
+    pub date: DateTime<Utc>,
+    pub words: usize,
+    pub headings: Vec<String>,
+    pub labels: Vec<String>,
+    /// Readable text of the page, without markup or math
+    pub text: String,
+    /// True if the page or any section containing it is a draft
+    pub draft: bool,
+    pub front_matter: FrontMatter,
+}
+
+impl Page {
+    /// Read the title, summary, date, headings and text of the page at `local_path`.
+    pub fn read(local_path: &str, ctx: &Context) -> MyResult<Page> {
+        let global_path = ctx.path(local_path);
+        let text = match fs::read_to_string(&global_path) {
+            Ok(t) => t,
+            Err(_) => return Err(format!("Could not read page {}", global_path).into())
+        };
+        let (front_matter, front_lines) = match FrontMatter::parse(&text) {
+            Ok(f) => f,
+            Err(d) => return Err(d.in_file(local_path))
+        };
+        let date = match front_matter.date {
+            Some(d) => d,
+            None => ctx.root.get_file_date(local_path),
+        };
+
+        let mut title = front_matter.title.clone();
+        let mut summary = front_matter.summary.clone();
+        let mut headings = Vec::new();
+        let mut labels = Vec::new();
+        let mut body = Vec::new();
+        // Set inside equations which open with $$ on one line and close on a later one
+        let mut in_equation = false;
+        for line in text.lines().skip(front_lines) {
+            if in_equation || line.starts_with("$$") {
+                if line.matches("$$").count() % 2 == 1 {
+                    in_equation = !in_equation;
+                }
+                continue;
+            }
+            if title.is_none() {
+                if let Some(t) = line.strip_prefix("# ") {
+                    title = Some(plain_text(t).trim().to_owned());
+                }
+            }
+            if summary.is_none() && is_paragraph(line) {
+                summary = Some(shorten(&plain_text(line).split_whitespace().collect::<Vec<_>>().join(" "), SUMMARY_LENGTH));
+            }
+            if let Some(label) = line.strip_prefix('~') {
+                labels.push(label.trim().to_owned());
+            } else if line.starts_with('#') {
+                headings.push(plain_text(line.trim_start_matches('#')).trim().to_owned());
+            }
+            if !line.starts_with('%') && !line.starts_with('~') {
+                // Leave out the characters which start headers, lists, quotes and figures
+                let line = line.strip_prefix("1.").unwrap_or(line).trim_start_matches(|c| "#>*!?".contains(c));
+                body.extend(plain_text(line).split_whitespace().map(|w| w.to_owned()));
+            }
+        }
+
+        let mut stem = Path::new(local_path).file_stem().expect("Incorrectly formatted path")
+            .to_str().expect("Incorrectly formatted path").to_owned();
+        if stem == "_toc" {
+            // Name tables of contents after their section
+            stem = match Path::new(local_path).parent().and_then(|p| p.file_name()).and_then(|n| n.to_str()) {
+                Some("text") | None => "Home".to_owned(),
+                Some(n) => n.to_owned(),
+            };
+        }
+
+        Ok(Page {
+            local_path: local_path.to_owned(),
+            title: title.unwrap_or(stem),
+            summary: summary.unwrap_or_default(),
+            date,
+            words: body.len(),
+            headings,
+            labels,
+            text: body.join(" "),
+            draft: front_matter.draft || ctx.settings(local_path).draft,
+            front_matter,
+        })
+    }
+
+    /// Name of the directory containing the page, starting with text
+    pub fn section_path(&self) -> &str {
+        Path::new(&self.local_path).parent().expect("Path had no parent")
+            .to_str().expect("Incorrectly formatted path")
+    }
+
+    /// Path of the compiled page, starting with html/
+    pub fn html_path(&self) -> String {
+        html_path(&self.local_path)
+    }
+
+    pub fn is_toc(&self) -> bool {
+        self.local_path.ends_with("_toc.md")
+    }
+
+    /// Estimated reading time in minutes
+    pub fn reading_time(&self) -> usize {
+        self.words.div_ceil(WORDS_PER_MINUTE).max(1)
+    }
+}
+
+/// Path of the page compiled from the markdown file at `local_path`, starting with html/.
+/// Tables of contents become index.html.
+pub fn html_path(local_path: &str) -> String {
+    if local_path.ends_with("_toc.md") {
+        format!("html/{}index.html", &local_path[5..local_path.len()-7])
+    } else {
+        format!("html/{}.html", &local_path[5..local_path.len()-3])
+    }
+}
+
+/// Returns true if the line is ordinary paragraph text rather than a command
+fn is_paragraph(line: &str) -> bool {
+    match line.chars().next() {
+        None => false,
+        Some(c) => !"#>`*!?$%~".contains(c) && !line.starts_with("1.")
+    }
+}
+
+fn shorten(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_owned();
+    }
+    let mut out = text.chars().take(length).collect::<String>();
+    if let Some(i) = out.rfind(' ') {
+        out.truncate(i);
+    }
+    out.push('…');
+    out
+}
+
+/// Strip markdown modifiers, math and link targets from a line, leaving the readable text. The
+/// text is not escaped.
+pub fn plain_text(line: &str) -> String {
+    let mut out = String::new();
+    let mut in_math = false;
+    let mut in_target = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if escaped {
+            out.push(c);
+            escaped = false;
+            continue;
+        }
+        if let Some(close) = in_target {
+            if c == close {
+                in_target = None;
+            }
+            continue;
+        }
+        match c {
+            '\\' if !in_math => escaped = true,
+            '$' => {
+                // $$ opens or closes display math, rather than two inline equations
+                if chars.peek() == Some(&'$') {
+                    chars.next();
+                }
+                in_math = !in_math;
+            },
+            _ if in_math => (),
+            '(' if previous == ']' => in_target = Some(')'),
+            '{' if previous == ']' => in_target = Some('}'),
+            '*' | '_' | '`' | '[' | ']' => (),
+            _ => out.push(c),
+        }
+        previous = c;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_strips_markup_and_link_targets() {
+        assert_eq!(plain_text("*Bold* and _italic_ `code`"), "Bold and italic code");
+        assert_eq!(plain_text("See [the page]{physics/spin} or [this](https://a.b)"), "See the page or this");
+        assert_eq!(plain_text("An escaped \\* star"), "An escaped * star");
+    }
+
+    #[test]
+    fn plain_text_strips_math() {
+        assert_eq!(plain_text("Energy $E = mc^2$ is conserved"), "Energy  is conserved");
+        assert_eq!(plain_text("$$ S = \\frac{\\hbar}{2}\\sigma $$"), "");
+        assert_eq!(plain_text("Before $$x$$ after $y$ end"), "Before  after  end");
+    }
+
+    #[test]
+    fn plain_text_does_not_escape() {
+        assert_eq!(plain_text("a < b & c"), "a < b & c");
+    }
+
+    #[test]
+    fn shorten_cuts_at_a_word() {
+        assert_eq!(shorten("short", 10), "short");
+        assert_eq!(shorten("one two three", 9), "one two…");
+    }
+}
@@ -0,0 +1,28 @@
+use crate::constants::MyResult;
+use crate::build::context::Context;
+use crate::build::file_queue::FileQueue;
+
+/// Write a page at the old address of every page moved with `wikid mv --redirect`, sending
+/// readers on to its new address.
+pub fn build_redirects(file_queue: &mut FileQueue, ctx: &Context) -> MyResult<()> {
+    for (from, to) in &ctx.root.redirects {
+        if file_queue.contains(from) {
+            // A page has since been written at the old address
+            continue;
+        }
+        let link = ctx.link(&format!("html/{}", to))?;
+        file_queue.add(from.clone(), format!("<!DOCTYPE html>
+<html>
+<head>
+    <meta charset=\"utf-8\">
+    <meta http-equiv=\"refresh\" content=\"0; url={link}\">
+    <title>Moved</title>
+</head>
+<body>
+    <p>This page has moved to <a href=\"{link}\">{link}</a>.</p>
+</body>
+</html>
+"));
+    }
+    Ok(())
+}
@@ -1,10 +1,12 @@
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::path::Path;
-use std::io::{self, BufRead};
 use crate::constants::MyResult;
 use crate::build::compile::{Command, CommandTypes};
-use crate::root::Root;
+use crate::build::context::Context;
+use crate::build::front_matter::FrontMatter;
+use crate::build::report::Report;
+use crate::diagnostics::{Diagnostic, codes};
 
 #[derive(Debug)]
 pub struct RefMap {
@@ -14,9 +16,44 @@ pub struct RefMap {
     figures: HashMap<String, (u32, String)>,// interior_Label, (eqn number, link)
     projects: HashMap<String, (String, String)>,// interior_label, (external_label, link)
     vocab: HashMap<String, String>,// display name, link
+    drafts: HashSet<String>,// labels defined in drafts, which cannot be linked to
+    ignored: HashSet<String>,// labels defined in ignored sections, which are linked to as plain text
+    sources: HashMap<String, (String, usize)>,// label, (path of the page, line number)
     public: bool,
 }
 
+/// The kinds of object a `~` label can be attached to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelKind {
+    Section,
+    Equation,
+    Figure,
+}
+
+impl LabelKind {
+    /// Short name, as used by `wikid list labels --kind`
+    pub fn name(&self) -> &'static str {
+        match self {
+            LabelKind::Section => "sec",
+            LabelKind::Equation => "eq",
+            LabelKind::Figure => "fig",
+        }
+    }
+}
+
+/// A label defined with `~` and what it points to
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub label: String,
+    pub kind: LabelKind,
+    /// Section number such as 2.1, or the number of the equation or figure
+    pub number: String,
+    /// Path of the page defining the label, starting with text/
+    pub local_path: String,
+    /// Line of the label, starting from 1
+    pub line: usize,
+}
+
 impl RefMap {
     pub fn new (public: bool) -> RefMap {
         let posts = HashMap::new();
@@ -25,22 +62,66 @@ impl RefMap {
         let projects = HashMap::new();
         let vocab = HashMap::new();
         let figures = HashMap::new();
+        let drafts = HashSet::new();
+        let ignored = HashSet::new();
+        let sources = HashMap::new();
 
-        RefMap { posts, secs, eqs, projects, vocab, figures, public }
+        RefMap { posts, secs, eqs, projects, vocab, figures, drafts, ignored, sources, public }
     }
 
-    /// Scan through file looking for sections, equations, projects, and vocab.
-    pub fn add_file(&mut self, local_path: &str) -> MyResult<()> {
-        let global_path = Root::get_path_from_local(local_path)?;
-        let file = match File::open(&global_path) {
+    /// Scan through file looking for sections, equations, projects, and vocab. The labels of draft
+    /// files are only recorded so that links to them can be reported.
+    pub fn add_file(&mut self, local_path: &str, draft: bool, ctx: &Context, report: &mut Report) -> MyResult<()> {
+        if draft {
+            let labels = self.hidden_labels(local_path, ctx, report)?;
+            self.drafts.extend(labels);
+            return Ok(());
+        }
+        let global_path = ctx.path(local_path);
+        let text = match fs::read_to_string(&global_path) {
+            Ok(t) => t,
+            Err(_) => return Err(format!("Compile tree was corrupted in refs: path {}", global_path).into())
+        };
+        self.scan(local_path, &text, &|l| ctx.link(l), report)
+    }
+
+    /// Record the labels of a page in an ignored section, so that links to them are left as plain
+    /// text. Problems with the page are not reported.
+    pub fn add_ignored(&mut self, local_path: &str, ctx: &Context) -> MyResult<()> {
+        let labels = self.hidden_labels(local_path, ctx, &mut Report::new())?;
+        self.ignored.extend(labels);
+        Ok(())
+    }
+
+    /// The labels of a page which is left out of the build
+    fn hidden_labels(&self, local_path: &str, ctx: &Context, report: &mut Report) -> MyResult<Vec<String>> {
+        let mut map = RefMap::new(self.public);
+        map.add_file(local_path, false, ctx, report)?;
+        Ok(map.posts.into_keys().chain(map.secs.into_keys()).chain(map.eqs.into_keys()).chain(map.figures.into_keys()).collect())
+    }
+
+    /// Record the labels in `text`, the markdown of the page at `local_path` (starting with text/),
+    /// without reading any files. Links point to the page's HTML file under `base_url`.
+    pub fn add_source(&mut self, local_path: &str, text: &str, base_url: &str) -> Vec<Diagnostic> {
+        let mut report = Report::new();
+        let base_url = base_url.trim_end_matches('/');
+        if let Err(d) = self.scan(local_path, text, &|l| Ok(format!("{}/{}", base_url, &l[5..])), &mut report) {
+            report.add(d);
+        }
+        report.into_diagnostics()
+    }
+
+    /// Scan through the text of a file. `link` turns a path starting with html/ into a URL.
+    fn scan(&mut self, local_path: &str, text: &str, link: &dyn Fn(&str) -> MyResult<String>, report: &mut Report) -> MyResult<()> {
+        let (front_matter, front_lines) = match FrontMatter::parse(text) {
             Ok(f) => f,
-            Err(_) => return Err(format!("Compile tree was corrupted in refs: path {}", global_path))
+            Err(d) => return Err(d.in_file(local_path))
         };
 
-        let file_name = Path::new(&global_path).file_name().expect("Incorrectly formatted path");
-        let root = Root::summon()?;
+        let file_name = Path::new(local_path).file_name().expect("Incorrectly formatted path");
 
         let mut label = "".to_owned();
+        let mut label_line = (0, "");// line number starting from 1, text
         let mut eq_num = 1;
         let mut fig_num = 1;
         let mut sec_num = [0; 5];
@@ -51,21 +132,21 @@ impl RefMap {
         }
 
         self.posts.insert((local_path[5..local_path.len()-3]).to_owned(), (
-            file_name.to_str().expect("Incorrectly formatted path").to_owned(),
-            root.get_link_from_local(&format!("{}.html", bare_link), self.public)?
+            match front_matter.title {
+                Some(t) => t,
+                None => file_name.to_str().expect("Incorrectly formatted path").to_owned(),
+            },
+            link(&format!("{}.html", bare_link))?
         ));
 
         // Write center material
-        for (line_num, line) in io::BufReader::new(file).lines().enumerate() {
-            let line = line.expect("Error in reading files");
+        for (line_num, line) in text.lines().enumerate().skip(front_lines) {
 
             let mut command = Command::new();
             let mut command_arg = "".to_owned();
             for c in line.chars() {
-                if !command.parse_command(c) {
-                    if c != ' ' {
-                        command_arg.push(c);
-                    }
+                if !command.parse_command(c) && c != ' ' {
+                    command_arg.push(c);
                 }
             }
 
@@ -86,28 +167,39 @@ impl RefMap {
             };
 
             if !label.is_empty() {
+                if self.secs.contains_key(&label) || self.eqs.contains_key(&label) || self.figures.contains_key(&label) {
+                    report.add(Diagnostic::warning(format!("Label {} was already defined", label))
+                        .code(codes::DUPLICATE_LABEL)
+                        .in_file(local_path)
+                        .at_line(label_line.0, label_line.1)
+                        .at_column(2, label_line.1.chars().count().saturating_sub(1))
+                        .help("Links to this label will point to the last definition"));
+                }
+                if matches!(command.c_type, CommandTypes::Header(_) | CommandTypes::MultiLatex | CommandTypes::Image) {
+                    self.sources.insert(label.to_owned(), (local_path.to_owned(), label_line.0));
+                }
                 match &command.c_type {
                     CommandTypes::Header(_) => {self.secs.insert(label.to_owned(), (
                         command_arg,
                         sec_num,
-                        root.get_link_from_local(
-                            &format!("{}.html-sec-{}", bare_link, sec_num.iter().map( |&n| n.to_string() + "-").collect::<String>()),
-                            self.public)?
+                        link(&format!("{}.html#sec-{}", bare_link, sec_num.iter().map( |&n| n.to_string() + "-").collect::<String>()))?
                     ));},
                     CommandTypes::MultiLatex => {self.eqs.insert(label.to_owned(), (
                         eq_num-1,
-                        root.get_link_from_local(
-                            &format!("{}.html#eq{}", bare_link, eq_num-1), self.public)?
+                        link(&format!("{}.html#eq{}", bare_link, eq_num-1))?
                     ));},
                     CommandTypes::Image => {self.figures.insert(label.to_owned(),
                         (
                             fig_num-1,
-                            root.get_link_from_local(
-                                &format!("{}.html#fig{}", bare_link, fig_num-1), self.public)?
+                            link(&format!("{}.html#fig{}", bare_link, fig_num-1))?
                         )
                     );},
                     _ => {
-                        println!("Unused label {} with command type {:?}", label, command.c_type);
+                        report.add(Diagnostic::warning(format!("Label {} is not followed by a header, equation, or figure", label))
+                            .code(codes::UNUSED_LABEL)
+                            .in_file(local_path)
+                            .at_line(label_line.0, label_line.1)
+                            .at_column(2, label_line.1.chars().count().saturating_sub(1)));
                     }
                 }
                 label = "".to_owned();
@@ -116,23 +208,27 @@ impl RefMap {
             if let Some(c) = line.chars().nth(0) {
                 if c == '~' {
                     // Label has been found
-                    label = line[1..].to_owned();
-                    loop {
-                        match label.chars().nth(0) {
-                            Some(c) => if c != ' ' { break } else { label = label[1..].to_owned() },
-                            None => return Err(format!("File {}:{}:0: Label line was empty", local_path, line_num+1))
-                        }
-                    }
-                    loop {
-                        match label.chars().last() {
-                            Some(c) => if c != ' ' { break } else { label = label[..label.len()-2].to_owned() },
-                            None => return Err("Label line was empty".to_owned())
-                        }
+                    label = line[1..].trim_matches(' ').to_owned();
+                    label_line = (line_num+1, line);
+                    if label.is_empty() {
+                        report.add(Diagnostic::error("Label line was empty")
+                            .code(codes::EMPTY_LABEL)
+                            .in_file(local_path)
+                            .at_line(line_num+1, line)
+                            .at_column(1, 1)
+                            .help("Write the name of the label after the ~"));
                     }
                 }
                 continue;
             }
         }
+        if !label.is_empty() {
+            report.add(Diagnostic::warning(format!("Label {} at the end of the file is not followed by anything", label))
+                .code(codes::UNUSED_LABEL)
+                .in_file(local_path)
+                .at_line(label_line.0, label_line.1)
+                .at_column(2, label_line.1.chars().count().saturating_sub(1)));
+        }
         Ok(())
     }
 
@@ -146,6 +242,50 @@ impl RefMap {
         unimplemented!();
     }
 
+    /// Returns true if the label was defined in a draft, and so left out of this build.
+    pub fn is_draft(&self, original_label: &str, local_path: Option<&str>) -> bool {
+        if let Some(s) = local_path {
+            if self.drafts.contains(&format!("{}/{}", s, original_label)) {
+                return true;
+            }
+        }
+        self.drafts.contains(original_label)
+    }
+
+    /// Returns true if the label was defined in an ignored section, and not in any built page.
+    pub fn is_ignored(&self, original_label: &str, local_path: Option<&str>) -> bool {
+        if self.get_link(original_label, local_path).is_some() {
+            return false;
+        }
+        if let Some(s) = local_path {
+            if self.ignored.contains(&format!("{}/{}", s, original_label)) {
+                return true;
+            }
+        }
+        self.ignored.contains(original_label)
+    }
+
+    /// Every section, equation and figure label, ordered by page and line.
+    pub fn labels(&self) -> Vec<Label> {
+        let mut labels = Vec::new();
+        for (label, (local_path, line)) in &self.sources {
+            let (kind, number) = if let Some((_, num, _)) = self.secs.get(label) {
+                let num = num.iter().skip_while(|&&n| n == 0).collect::<Vec<_>>();
+                let len = num.len() - num.iter().rev().take_while(|&&&n| n == 0).count();
+                (LabelKind::Section, num[..len].iter().map(|n| n.to_string()).collect::<Vec<_>>().join("."))
+            } else if let Some((num, _)) = self.eqs.get(label) {
+                (LabelKind::Equation, num.to_string())
+            } else if let Some((num, _)) = self.figures.get(label) {
+                (LabelKind::Figure, num.to_string())
+            } else {
+                continue;
+            };
+            labels.push(Label { label: label.clone(), kind, number, local_path: local_path.clone(), line: *line });
+        }
+        labels.sort_by(|a, b| (&a.local_path, a.line).cmp(&(&b.local_path, b.line)));
+        labels
+    }
+
     /// Get the text label and HTML link for a hyperlink.
     pub fn get_link(&self, original_label: &str, local_path: Option<&str>) -> Option<(String, String)> {
         // Try a global path
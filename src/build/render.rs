@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::Path;
+use crate::constants::MyResult;
+use crate::diagnostics::Diagnostic;
+use crate::build::refs::RefMap;
+use crate::build::file_queue::FileQueue;
+use crate::build::front_matter::FrontMatter;
+use crate::build::compile::{ParseState, parse_line};
+
+/// Settings for rendering markdown outside a wiki.
+pub struct RenderSettings {
+    /// Path the markdown would have in a wiki, starting with text/. Used to resolve labels relative
+    /// to the page's section.
+    pub path: String,
+    /// URL which images given as `![Caption]{image.png}` are linked under
+    pub asset_url: String,
+    /// Resolve links as in a public build
+    pub public: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings { path: "text/page.md".to_owned(), asset_url: String::new(), public: false }
+    }
+}
+
+/// The result of rendering a page
+pub struct Rendered {
+    /// The body of the page, without the surrounding html, head or navigation
+    pub html: String,
+    /// Title given in the front matter
+    pub title: Option<String>,
+    /// Every problem found while rendering, in order
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Render a string of wikid markdown to HTML, resolving `{}` links with `ref_map`. Neither the
+/// current directory nor any files are read. Lines with errors are left out of the output and
+/// reported in the diagnostics.
+pub fn render_str(text: &str, ref_map: &RefMap, settings: &RenderSettings) -> Rendered {
+    let mut diagnostics = Vec::new();
+    let (front_matter, front_lines) = match FrontMatter::parse(text) {
+        Ok(f) => f,
+        Err(d) => {
+            diagnostics.push(d.in_file(&settings.path));
+            (FrontMatter::default(), 0)
+        }
+    };
+
+    let mut parse_state = ParseState::new(&settings.path, false);
+    parse_state.asset_url = Some(settings.asset_url.clone());
+    let parent_path = Path::new(&settings.path).parent().and_then(|p| p.to_str()).unwrap_or("").to_owned();
+    let local_parent_path = if parent_path.len() <= 4 {
+        None
+    } else {
+        Some(&parent_path[5..])
+    };
+
+    let mut html = String::new();
+    for (line_num, line) in text.lines().enumerate().skip(front_lines) {
+        match parse_line(line.to_owned(), ref_map, &mut parse_state, settings.public, local_parent_path) {
+            Ok(l) => html.push_str(&l),
+            Err(d) => diagnostics.push(d.in_file(&settings.path).at_line(line_num+1, line)),
+        };
+        html.push('\n');
+        for diagnostic in parse_state.diagnostics.drain(..) {
+            diagnostics.push(diagnostic.in_file(&settings.path).at_line(line_num+1, line));
+        }
+    }
+    // Images are linked rather than copied, so the queue is never written
+    html.push_str(&parse_state.terminal(&mut FileQueue::new()));
+
+    Rendered { html, title: front_matter.title, diagnostics }
+}
+
+/// Render the wikid markdown file at `path`, which need not be inside a wiki. Only that file is
+/// read.
+pub fn render_file(path: &str, ref_map: &RefMap, settings: &RenderSettings) -> MyResult<Rendered> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(_) => return Err(format!("Could not read {}", path).into())
+    };
+    Ok(render_str(&text, ref_map, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::codes;
+
+    #[test]
+    fn misordered_modifiers_in_link_text_are_reported() {
+        let rendered = render_str("[*a_b*](https://x.org)", &RefMap::new(false), &RenderSettings::default());
+        assert_eq!(rendered.diagnostics.len(), 1);
+        assert_eq!(rendered.diagnostics[0].code_str(), Some(codes::MODIFIER_ORDER));
+    }
+
+    #[test]
+    fn checking_continues_after_misordered_modifiers_in_link_text() {
+        let mut parse_state = ParseState::new("text/page.md", true);
+        let html = parse_line("[*a_b*](https://x.org) after".to_owned(), &RefMap::new(false), &mut parse_state, false, None).unwrap();
+        assert!(html.contains("after"));
+        assert!(parse_state.diagnostics.iter().any(|d| d.code_str() == Some(codes::MODIFIER_ORDER)));
+    }
+}
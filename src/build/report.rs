@@ -0,0 +1,38 @@
+use crate::diagnostics::Diagnostic;
+
+/// Problems found while building or checking the wiki. Every problem is collected so that they
+/// can all be reported at once.
+pub struct Report {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report { diagnostics: Vec::new() }
+    }
+
+    pub fn add(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn errors(&self) -> usize {
+        self.diagnostics.iter().filter(|d| d.is_error()).count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.diagnostics.iter().filter(|d| !d.is_error()).count()
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    /// Print every diagnostic to stderr, ordered by file and line.
+    pub fn print(&mut self) {
+        self.diagnostics.sort_by(|a, b| a.location().cmp(&b.location()));
+        for diagnostic in &self.diagnostics {
+            diagnostic.eprint();
+            eprintln!();
+        }
+    }
+}
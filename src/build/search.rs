@@ -0,0 +1,118 @@
+use serde_json::json;
+use crate::build::context::Context;
+use crate::constants::MyResult;
+use crate::build::file_queue::FileQueue;
+use crate::build::pages::Page;
+use crate::build::compile::{page_start, page_end};
+
+/// The search box shown in the header of every page.
+pub fn search_box(ctx: &Context) -> MyResult<String> {
+    Ok(format!("<form class=\"search-box\" action=\"{}\"><input type=\"search\" name=\"q\" placeholder=\"Search\"></form>",
+        ctx.link("html/search.html")?))
+}
+
+/// Write the search index to `search.js` and the page which queries it to `search.html`. The index
+/// is a script rather than JSON so that it can be loaded from file:// links.
+pub fn build_search(pages: &[Page], file_queue: &mut FileQueue, ctx: &Context) -> MyResult<()> {
+
+    let mut entries = Vec::new();
+    for page in pages {
+        entries.push(json!({
+            "title": page.title,
+            "url": ctx.link(&page.html_path())?,
+            "headings": page.headings,
+            "labels": page.labels,
+            "text": page.text,
+        }));
+    }
+    let index = match serde_json::to_string(&entries) {
+        Ok(s) => s,
+        Err(_) => return Err("Could not write the search index".into())
+    };
+    file_queue.add("search.js".to_owned(), format!("var SEARCH_INDEX = {};\n", index));
+
+    let css_name = ctx.link("html/css/text.css")?;
+    let header = format!("<h2><a href=\"{}\">Home</a></h2>{}",
+        ctx.link("html/index.html")?, search_box(ctx)?);
+    let mut text = page_start(&css_name, &format!("Search - {}", ctx.root.name), "", &header);
+    text.push_str(&format!(r#"<h1>Search</h1>
+<input type="search" id="search-query" placeholder="Search the wiki">
+<div id="search-results"></div>
+<script src="{index_link}"></script>
+<script>
+function searchWiki(query) {{
+    var terms = query.toLowerCase().split(/\s+/).filter(function(t) {{ return t.length > 0; }});
+    var results = [];
+    if (terms.length == 0) {{
+        return results;
+    }}
+    SEARCH_INDEX.forEach(function(page) {{
+        var title = page.title.toLowerCase();
+        var headings = page.headings.join(" ").toLowerCase();
+        var labels = page.labels.join(" ").toLowerCase();
+        var text = page.text.toLowerCase();
+        var score = 0;
+        for (var i = 0; i < terms.length; i++) {{
+            var termScore = 0;
+            if (title.indexOf(terms[i]) >= 0) termScore += 10;
+            if (headings.indexOf(terms[i]) >= 0) termScore += 5;
+            if (labels.indexOf(terms[i]) >= 0) termScore += 5;
+            if (text.indexOf(terms[i]) >= 0) termScore += 1;
+            if (termScore == 0) return;
+            score += termScore;
+        }}
+        results.push({{page: page, score: score, term: terms[0]}});
+    }});
+    results.sort(function(a, b) {{ return b.score - a.score; }});
+    return results;
+}}
+
+function snippet(text, term) {{
+    var i = text.toLowerCase().indexOf(term);
+    if (i < 0) {{
+        return text.substring(0, 200);
+    }}
+    var start = Math.max(0, i - 100);
+    return (start > 0 ? "\u2026" : "") + text.substring(start, i + 100) + "\u2026";
+}}
+
+// Text from the index is only ever set as textContent, so that it cannot inject markup
+function element(tag, text) {{
+    var e = document.createElement(tag);
+    if (text !== undefined) {{
+        e.textContent = text;
+    }}
+    return e;
+}}
+
+function showResults(query) {{
+    var results = searchWiki(query);
+    var out = document.getElementById("search-results");
+    out.replaceChildren();
+    results.forEach(function(r) {{
+        var entry = element("div");
+        entry.className = "index-entry";
+        var heading = element("h3");
+        var link = element("a", r.page.title);
+        link.href = r.page.url;
+        heading.appendChild(link);
+        entry.appendChild(heading);
+        entry.appendChild(element("p", snippet(r.page.text, r.term)));
+        out.appendChild(entry);
+    }});
+    if (query.trim().length > 0 && results.length == 0) {{
+        out.appendChild(element("p", "No pages matched."));
+    }}
+}}
+
+var box = document.getElementById("search-query");
+box.value = new URLSearchParams(window.location.search).get("q") || "";
+box.addEventListener("input", function() {{ showResults(box.value); }});
+showResults(box.value);
+</script>
+"#, index_link=ctx.link("html/search.js")?));
+    text.push_str(&page_end(&ctx.root)?);
+    file_queue.add("search.html".to_owned(), text);
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::fs;
+use std::collections::HashMap;
+use crate::root::Root;
+use crate::build::context::Context;
+use crate::constants::MyResult;
+use crate::build::file_queue::FileQueue;
+use crate::build::pages::Page;
+use crate::build::feed::xml_escape;
+
+/// The tag giving the public URL of the page at `html_path`, placed in the page head.
+pub fn canonical_link(html_path: &str, root: &Root) -> MyResult<String> {
+    Ok(format!("\n    <link rel=\"canonical\" href=\"{}\">", root.get_link_from_local(html_path, true)?))
+}
+
+/// Write sitemap.xml listing every HTML page in the file queue, and a robots.txt pointing to it.
+/// The robots.txt starts with the contents of the file named by `robots` in wikid.json, if set.
+pub fn build_sitemap(pages: &[Page], file_queue: &mut FileQueue, ctx: &Context) -> MyResult<()> {
+    let root = &ctx.root;
+    let dates = pages.iter()
+        .map(|p| (p.html_path()[5..].to_owned(), p.date))
+        .collect::<HashMap<_, _>>();
+
+    let mut sitemap = "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n".to_owned();
+    for html_name in file_queue.html_files() {
+        let lastmod = match dates.get(&html_name) {
+            Some(d) => format!("<lastmod>{}</lastmod>", d.format("%Y-%m-%d")),
+            None => String::new(),
+        };
+        sitemap.push_str(&format!("    <url><loc>{}</loc>{}</url>\n",
+            xml_escape(&root.get_link_from_local(&format!("html/{}", html_name), true)?), lastmod));
+    }
+    sitemap.push_str("</urlset>\n");
+
+    let mut robots = match &root.robots {
+        Some(path) => match fs::read_to_string(ctx.path(path)) {
+            Ok(t) => t,
+            Err(_) => return Err(format!("Could not read the robots.txt file {}", path).into())
+        },
+        None => "User-agent: *\nAllow: /\n".to_owned(),
+    };
+    if !robots.ends_with('\n') {
+        robots.push('\n');
+    }
+    robots.push_str(&format!("Sitemap: {}\n", root.get_link_from_local("html/sitemap.xml", true)?));
+
+    file_queue.add("sitemap.xml".to_owned(), sitemap);
+    file_queue.add("robots.txt".to_owned(), robots);
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use crate::build::context::Context;
+use crate::constants::MyResult;
+use crate::build::file_queue::FileQueue;
+use crate::build::pages::Page;
+use crate::build::index::index_entries;
+use crate::build::compile::{page_start, page_end, html_escape};
+use crate::build::sitemap::canonical_link;
+use crate::build::search::search_box;
+
+/// Name of the HTML file for a tag, with spaces and punctuation replaced by dashes. Tags with no
+/// letters or digits are named by their bytes in hex, and a tag named index is renamed so that it
+/// does not replace the tag cloud.
+pub fn tag_slug(tag: &str) -> String {
+    let mut slug = String::new();
+    for c in tag.trim().to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    match slug.trim_matches('-') {
+        "" => format!("tag-{}", tag.trim().bytes().map(|b| format!("{:02x}", b)).collect::<String>()),
+        "index" => "tag-index".to_owned(),
+        s => s.to_owned(),
+    }
+}
+
+/// Links to the page of each tag, shown beneath the page header.
+pub fn tag_chips(tags: &[String], ctx: &Context) -> MyResult<String> {
+    if tags.is_empty() {
+        return Ok(String::new());
+    }
+    let mut out = "<div class=\"tags\">".to_owned();
+    for tag in tags {
+        let link = ctx.link(&format!("html/tags/{}.html", tag_slug(tag)))?;
+        out.push_str(&format!("<a class=\"tag\" href=\"{}\">{}</a>", link, html_escape(tag)));
+    }
+    out.push_str("</div>");
+    Ok(out)
+}
+
+/// Write a page listing the pages with each tag, and a tag cloud linking to all of them.
+pub fn build_tag_pages(pages: &[Page], file_queue: &mut FileQueue, ctx: &Context) -> MyResult<()> {
+    // slug, (display name, pages)
+    let mut tags: BTreeMap<String, (String, Vec<&Page>)> = BTreeMap::new();
+    for page in pages {
+        for tag in &page.front_matter.tags {
+            tags.entry(tag_slug(tag)).or_insert_with(|| (tag.clone(), Vec::new())).1.push(page);
+        }
+    }
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    let css_name = ctx.link("html/css/text.css")?;
+    let cloud_link = ctx.link("html/tags/index.html")?;
+    let header = format!("<h2><a href=\"{}\">Home</a> > <a href=\"{}\">Tags</a></h2>{}",
+        ctx.link("html/index.html")?, cloud_link, search_box(ctx)?);
+
+    let max_count = tags.values().map(|(_, p)| p.len()).max().unwrap_or(1);
+    let mut cloud = "<h1>Tags</h1><div class=\"tag-cloud\">".to_owned();
+    for (slug, (name, tag_pages)) in &mut tags {
+        tag_pages.sort_by_key(|p| Reverse(p.date));
+
+        let meta = match ctx.public {
+            true => canonical_link(&format!("html/tags/{}.html", slug), &ctx.root)?,
+            false => String::new(),
+        };
+        let mut text = page_start(&css_name, &format!("{} - {}", name, ctx.root.name), &meta, &header);
+        text.push_str(&format!("<h1>Pages tagged &ldquo;{}&rdquo;</h1>", html_escape(name)));
+        text.push_str(&index_entries(tag_pages, ctx)?);
+        text.push_str(&page_end(&ctx.root)?);
+        file_queue.add(format!("tags/{}.html", slug), text);
+
+        // Scale the font between 100% and 200% by the number of pages
+        let size = 100 + 100 * (tag_pages.len() - 1) / max_count.max(2).saturating_sub(1);
+        cloud.push_str(&format!("<a class=\"tag\" style=\"font-size: {}%\" href=\"{}\">{} ({})</a> ",
+            size, ctx.link(&format!("html/tags/{}.html", slug))?, html_escape(name), tag_pages.len()));
+    }
+    cloud.push_str("</div>");
+
+    let meta = match ctx.public {
+        true => canonical_link("html/tags/index.html", &ctx.root)?,
+        false => String::new(),
+    };
+    let mut text = page_start(&css_name, &format!("Tags - {}", ctx.root.name), &meta, &header);
+    text.push_str(&cloud);
+    text.push_str(&page_end(&ctx.root)?);
+    file_queue.add("tags/index.html".to_owned(), text);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_slug_replaces_punctuation() {
+        assert_eq!(tag_slug("Physics"), "physics");
+        assert_eq!(tag_slug(" Quantum  Mechanics "), "quantum-mechanics");
+        assert_eq!(tag_slug("C++ / Rust!"), "c-rust");
+        assert_eq!(tag_slug("Élan vital"), "élan-vital");
+        assert_eq!(tag_slug("???"), "tag-3f3f3f");
+        assert_eq!(tag_slug("Index"), "tag-index");
+    }
+}
@@ -0,0 +1,315 @@
+use clap::{Parser, Subcommand};
+use std::fs;
+use std::str::FromStr;
+use crate::constants::*;
+use crate::root::Root;
+use crate::section::Section;
+use crate::build::SortKey;
+
+/// Settings of wikid.json, in the order they are listed
+const ROOT_KEYS: [&str; 18] = ["name", "public_url", "bg_image", "bg_fit", "bg_dim", "bg_blur", "bg_color", "fonts", "main_color", "dark_mode", "font_size", "index_sort", "rss", "robots", "description", "browser", "theme", "custom_css"];
+/// Settings of a section's .wikid.json. All but the first three override those of wikid.json.
+const SECTION_KEYS: [&str; 15] = ["color", "ignore", "draft", "dark_mode", "fonts", "font_size", "bg_image", "bg_fit", "bg_dim", "bg_blur", "bg_color", "description", "index_sort", "theme", "custom_css"];
+
+#[derive(Parser)]
+pub struct ConfigSettings {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print a setting. Section settings are named like physics.color
+    Get {
+        key: String,
+    },
+    /// Change a setting. An empty value unsets optional settings, and section settings which
+    /// override those of the wiki
+    Set {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+    /// Print every setting of the wiki and its sections
+    List,
+}
+
+fn parse_bool(key: &str, value: &str) -> MyResult<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("{} must be true or false", key).into())
+    }
+}
+
+fn parse_color(value: &str) -> MyResult<String> {
+    match Color::from_str(value.trim_end()) {
+        Ok(c) => Ok(c.to_string()),
+        Err(_) => Err("Please give a proper hex-formatted color (e.g., #abcdef).".into())
+    }
+}
+
+/// None for an empty value, which unsets an optional setting
+fn optional(value: &str) -> Option<String> {
+    match value.is_empty() {
+        true => None,
+        false => Some(value.to_owned()),
+    }
+}
+
+fn parse_bg_image(value: &str) -> MyResult<Option<String>> {
+    let extension = value.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default();
+    if !value.is_empty() && !BG_IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(format!("The background image {} must be one of {}", value, BG_IMAGE_EXTENSIONS.join(", ")).into());
+    }
+    Ok(optional(value))
+}
+
+fn parse_bg_fit(value: &str) -> MyResult<Option<String>> {
+    if !value.is_empty() && !BG_FITS.contains(&value) {
+        return Err(format!("bg_fit must be one of {}", BG_FITS.join(", ")).into());
+    }
+    Ok(optional(value))
+}
+
+fn parse_bg_dim(value: &str) -> MyResult<Option<u32>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse::<u32>() {
+        Ok(d) if d <= 100 => Ok(Some(d)),
+        _ => Err("bg_dim must be a whole percentage from 0 to 100".into())
+    }
+}
+
+fn parse_bg_blur(value: &str) -> MyResult<Option<u32>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse::<u32>() {
+        Ok(b) => Ok(Some(b)),
+        _ => Err("bg_blur must be a whole number of pixels".into())
+    }
+}
+
+fn parse_bg_color(value: &str) -> MyResult<Option<String>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(parse_color(value)?))
+}
+
+fn parse_fonts(value: &str) -> Option<Vec<String>> {
+    optional(value).map(|v| v.split(',').map(|f| f.trim().to_owned()).filter(|f| !f.is_empty()).collect())
+}
+
+fn parse_font_size(value: &str) -> MyResult<u32> {
+    match value.parse::<u32>() {
+        Ok(s) if s > 0 => Ok(s),
+        _ => Err("font_size must be a positive whole number of pixels".into())
+    }
+}
+
+fn parse_index_sort(value: &str) -> MyResult<Option<String>> {
+    if !value.is_empty() {
+        SortKey::parse(value)?;
+    }
+    Ok(optional(value))
+}
+
+fn parse_theme(root: &Root, value: &str) -> MyResult<Option<String>> {
+    let value = value.trim();
+    if !value.is_empty() {
+        crate::theme::template(value, root)?;
+    }
+    Ok(optional(value))
+}
+
+fn parse_custom_css(root: &Root, value: &str) -> MyResult<Option<String>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if !value.ends_with(".css") {
+        return Err(format!("The custom CSS file {} does not end in .css", value).into());
+    }
+    if fs::metadata(root.path(value)).is_err() {
+        return Err(format!("Could not find the custom CSS file {}", value).into());
+    }
+    Ok(optional(value))
+}
+
+fn show_fonts(fonts: &Option<Vec<String>>) -> String {
+    fonts.as_ref().map(|f| f.join(", ")).unwrap_or_default()
+}
+
+fn get_root_value(root: &Root, key: &str) -> MyResult<String> {
+    Ok(match key {
+        "name" => root.name.clone(),
+        "public_url" => root.public_url.clone(),
+        "bg_image" => root.bg_image.clone().unwrap_or_default(),
+        "bg_fit" => root.bg_fit.clone().unwrap_or_default(),
+        "bg_dim" => root.bg_dim.map(|d| d.to_string()).unwrap_or_default(),
+        "bg_blur" => root.bg_blur.map(|b| b.to_string()).unwrap_or_default(),
+        "bg_color" => root.bg_color.clone().unwrap_or_default(),
+        "fonts" => show_fonts(&root.fonts),
+        "main_color" => root.main_color.clone(),
+        "dark_mode" => root.dark_mode.map(|d| d.to_string()).unwrap_or_default(),
+        "font_size" => root.font_size.to_string(),
+        "index_sort" => root.index_sort.clone().unwrap_or_default(),
+        "rss" => root.rss.to_string(),
+        "robots" => root.robots.clone().unwrap_or_default(),
+        "description" => root.description.clone().unwrap_or_default(),
+        "browser" => root.browser.clone().unwrap_or_default(),
+        "theme" => root.theme.clone().unwrap_or_default(),
+        "custom_css" => root.custom_css.clone().unwrap_or_default(),
+        _ => return Err(format!("Unknown setting {}. Use one of {}, or a section setting such as physics.color", key, ROOT_KEYS.join(", ")).into())
+    })
+}
+
+fn set_root_value(root: &mut Root, key: &str, value: &str) -> MyResult<()> {
+    match key {
+        "name" => {
+            if value.trim().is_empty() {
+                return Err("The name cannot be empty".into());
+            }
+            root.name = value.trim().to_owned();
+        },
+        "public_url" => {
+            if !value.is_empty() && !value.starts_with("https://") && !value.starts_with("http://") {
+                return Err("The public url must start with https:// or http://".into());
+            }
+            root.public_url = value.trim_end_matches('/').to_owned();
+        },
+        "bg_image" => root.bg_image = parse_bg_image(value)?,
+        "bg_fit" => root.bg_fit = parse_bg_fit(value)?,
+        "bg_dim" => root.bg_dim = parse_bg_dim(value)?,
+        "bg_blur" => root.bg_blur = parse_bg_blur(value)?,
+        "bg_color" => root.bg_color = parse_bg_color(value)?,
+        "fonts" => root.fonts = parse_fonts(value),
+        "main_color" => root.main_color = parse_color(value)?,
+        "dark_mode" => root.dark_mode = match value.is_empty() {
+            true => None,
+            false => Some(parse_bool(key, value)?),
+        },
+        "font_size" => root.font_size = parse_font_size(value)?,
+        "index_sort" => root.index_sort = parse_index_sort(value)?,
+        "rss" => root.rss = parse_bool(key, value)?,
+        "robots" => {
+            if !value.is_empty() && fs::metadata(root.path(value)).is_err() {
+                return Err(format!("Could not find the robots.txt file {}", value).into());
+            }
+            root.robots = optional(value);
+        },
+        "description" => root.description = optional(value.trim()),
+        "browser" => root.browser = optional(value.trim()),
+        "theme" => root.theme = parse_theme(root, value)?,
+        "custom_css" => root.custom_css = parse_custom_css(root, value)?,
+        _ => return Err(format!("Unknown setting {}. Use one of {}, or a section setting such as physics.color", key, ROOT_KEYS.join(", ")).into())
+    };
+    Ok(())
+}
+
+/// Split a section setting such as physics.color into the section directory and the setting
+fn split_section_key(key: &str) -> Option<(String, &str)> {
+    let (section, setting) = key.rsplit_once('.')?;
+    Some((format!("text/{}", section), setting))
+}
+
+fn load_section(root: &Root, local_dir: &str) -> MyResult<Section> {
+    match Section::load(root, local_dir)? {
+        Some(s) => Ok(s),
+        None => Err(format!("There is no section {}", &local_dir[5..]).into())
+    }
+}
+
+fn get_section_value(section: &Section, setting: &str) -> MyResult<String> {
+    Ok(match setting {
+        "color" => section.color.clone(),
+        "ignore" => section.ignore.to_string(),
+        "draft" => section.draft.to_string(),
+        "dark_mode" => section.overrides.dark_mode.map(|d| d.to_string()).unwrap_or_default(),
+        "fonts" => show_fonts(&section.overrides.fonts),
+        "font_size" => section.overrides.font_size.map(|s| s.to_string()).unwrap_or_default(),
+        "bg_image" => section.overrides.bg_image.clone().unwrap_or_default(),
+        "bg_fit" => section.overrides.bg_fit.clone().unwrap_or_default(),
+        "bg_dim" => section.overrides.bg_dim.map(|d| d.to_string()).unwrap_or_default(),
+        "bg_blur" => section.overrides.bg_blur.map(|b| b.to_string()).unwrap_or_default(),
+        "bg_color" => section.overrides.bg_color.clone().unwrap_or_default(),
+        "description" => section.overrides.description.clone().unwrap_or_default(),
+        "index_sort" => section.overrides.index_sort.clone().unwrap_or_default(),
+        "theme" => section.overrides.theme.clone().unwrap_or_default(),
+        "custom_css" => section.overrides.custom_css.clone().unwrap_or_default(),
+        _ => return Err(format!("Unknown section setting {}. Use one of {}", setting, SECTION_KEYS.join(", ")).into())
+    })
+}
+
+fn set_section_value(root: &Root, section: &mut Section, setting: &str, value: &str) -> MyResult<()> {
+    match setting {
+        "color" => section.color = match value.is_empty() {
+            true => String::new(),
+            false => parse_color(value)?,
+        },
+        "ignore" => section.ignore = parse_bool(setting, value)?,
+        "draft" => section.draft = parse_bool(setting, value)?,
+        // An empty value inherits the setting again
+        "dark_mode" => section.overrides.dark_mode = match value.is_empty() {
+            true => None,
+            false => Some(parse_bool(setting, value)?),
+        },
+        "fonts" => section.overrides.fonts = parse_fonts(value),
+        "font_size" => section.overrides.font_size = match value.is_empty() {
+            true => None,
+            false => Some(parse_font_size(value)?),
+        },
+        "bg_image" => section.overrides.bg_image = parse_bg_image(value)?,
+        "bg_fit" => section.overrides.bg_fit = parse_bg_fit(value)?,
+        "bg_dim" => section.overrides.bg_dim = parse_bg_dim(value)?,
+        "bg_blur" => section.overrides.bg_blur = parse_bg_blur(value)?,
+        "bg_color" => section.overrides.bg_color = parse_bg_color(value)?,
+        "description" => section.overrides.description = optional(value.trim()),
+        "index_sort" => section.overrides.index_sort = parse_index_sort(value)?,
+        "theme" => section.overrides.theme = parse_theme(root, value)?,
+        "custom_css" => section.overrides.custom_css = parse_custom_css(root, value)?,
+        _ => return Err(format!("Unknown section setting {}. Use one of {}", setting, SECTION_KEYS.join(", ")).into())
+    };
+    Ok(())
+}
+
+impl ConfigSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        let mut root = Root::summon(chosen)?;
+        match &self.command {
+            ConfigCommand::Get { key } => {
+                let value = match split_section_key(key) {
+                    Some((dir, setting)) => get_section_value(&load_section(&root, &dir)?, setting)?,
+                    None => get_root_value(&root, key)?,
+                };
+                println!("{}", value);
+            },
+            ConfigCommand::Set { key, value } => {
+                match split_section_key(key) {
+                    Some((dir, setting)) => {
+                        let mut section = load_section(&root, &dir)?;
+                        set_section_value(&root, &mut section, setting, value)?;
+                        section.write(&root, &dir)?;
+                    },
+                    None => {
+                        set_root_value(&mut root, key, value)?;
+                        root.write()?;
+                    }
+                };
+            },
+            ConfigCommand::List => {
+                for key in ROOT_KEYS {
+                    println!("{} = {}", key, get_root_value(&root, key)?);
+                }
+                for section in root.get_sections()? {
+                    for setting in SECTION_KEYS {
+                        println!("{}.{} = {}", &section.path[5..], setting, get_section_value(&section, setting)?);
+                    }
+                }
+            },
+        };
+        Ok(())
+    }
+}
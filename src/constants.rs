@@ -1,11 +1,16 @@
+use std::fmt;
 use std::str::FromStr;
 use serde::{Serialize, Deserialize, Serializer};
 
-use crate::root::Root;
+pub use crate::diagnostics::Diagnostic;
 
-pub type MyResult<T> = Result<T, String>;
+pub type MyResult<T> = Result<T, Diagnostic>;
 
 const LIGHT_SHRINK: f32 = 0.2;
+/// Extensions of the files which can be background images
+pub const BG_IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "svg"];
+/// Ways a background image can fill the page. The first is the default.
+pub const BG_FITS: [&str; 4] = ["stretch", "cover", "contain", "tile"];
 
 #[derive(Debug, Deserialize)]
 pub struct Color {
@@ -24,9 +29,8 @@ impl Color {
         }
     }
 
-    pub fn light(&self) -> Color {
-        let root = Root::summon().unwrap();
-        if root.dark_mode {
+    pub fn light(&self, dark_mode: bool) -> Color {
+        if dark_mode {
             Color {
                 r: (LIGHT_SHRINK * self.r as f32) as u8,
                 g: (LIGHT_SHRINK * self.g as f32) as u8,
@@ -41,28 +45,33 @@ impl Color {
         }
     }
 
-    pub(crate) fn text(&self) -> Color {
-        let root = Root::summon().unwrap();
-        if root.dark_mode  {
+    pub(crate) fn text(&self, dark_mode: bool) -> Color {
+        if dark_mode  {
             Color { r: 0xff,g: 0xf0,b: 0xf0}
         } else {
             Color { r: 0x0,g: 0x0,b: 0x0}
         }
     }
 
-    pub(crate) fn bg(&self) -> Color {
-        let root = Root::summon().unwrap();
-        if root.dark_mode  {
+    pub(crate) fn bg(&self, dark_mode: bool) -> Color {
+        if dark_mode  {
             Color { r: 0x08,g: 0x08,b: 0x08}
         } else {
             Color { r: 0xff,g: 0xff,b: 0xff}
         }
     }
+
+    /// The text, background and light colors of the light or dark theme, as CSS custom properties
+    pub(crate) fn palette(&self, dark_mode: bool) -> String {
+        format!("--text: {}; --bg: {}; --light: {}; --theme: {};",
+            self.text(dark_mode), self.bg(dark_mode),
+            self.light(dark_mode), if dark_mode { "dark" } else { "light" })
+    }
 }
 
-impl ToString for Color {
-    fn to_string(&self) -> String {
-        format!("#{}{}{}", if self.r > 0 {format!("{:02x}", self.r)} else {"00".to_owned()},
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}{}{}", if self.r > 0 {format!("{:02x}", self.r)} else {"00".to_owned()},
                             if self.g > 0 {format!("{:02x}", self.g)} else {"00".to_owned()},
                             if self.b > 0 {format!("{:02x}", self.b)} else {"00".to_owned()})
     }
@@ -71,7 +80,7 @@ impl ToString for Color {
 impl FromStr for Color {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if match s.bytes().nth(0) {
+        if match s.as_bytes().first().copied() {
             None => return Err("Color must be 7 characters long"),
             Some(c) => c
         } != 35 {// Pound
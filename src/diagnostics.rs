@@ -0,0 +1,216 @@
+use std::fmt;
+use std::io::{self, IsTerminal};
+
+/// Codes identifying each kind of problem in a wiki's source, so that they can be looked up in
+/// the README. Errors start with E and warnings with W.
+pub mod codes {
+    pub const UNRESOLVED_LINK: &str = "E001";
+    pub const DRAFT_LINK: &str = "E002";
+    pub const MISSING_IMAGE: &str = "E003";
+    pub const MISSING_APPLET: &str = "E004";
+    pub const EMPTY_LABEL: &str = "E005";
+    pub const MODIFIER_ORDER: &str = "E006";
+    pub const FRONT_MATTER: &str = "E007";
+    pub const BAD_ARGUMENT: &str = "E008";
+    pub const MISSING_CSS: &str = "E009";
+    pub const DUPLICATE_LABEL: &str = "W001";
+    pub const UNUSED_LABEL: &str = "W002";
+    pub const ORPHAN_PAGE: &str = "W003";
+    pub const UNCLOSED_MODIFIER: &str = "W004";
+    pub const MALFORMED_URL: &str = "W005";
+    pub const DANGLING_LINK: &str = "W006";
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while running wikid, optionally pointing to the place in the source that
+/// caused it. Boxed to keep results small.
+#[derive(Clone, Debug)]
+pub struct Diagnostic(Box<Contents>);
+
+#[derive(Clone, Debug)]
+struct Contents {
+    severity: Severity,
+    code: Option<&'static str>,
+    message: String,
+    /// Path to the file, starting with text/
+    file: Option<String>,
+    /// Line number, starting from 1
+    line: Option<usize>,
+    /// Column in characters, starting from 1
+    column: Option<usize>,
+    /// Number of characters to underline, starting at the column
+    length: usize,
+    /// Text of the offending line
+    source: Option<String>,
+    help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic(Box::new(Contents {
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            length: 1,
+            source: None,
+            help: None,
+        }))
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(message);
+        diagnostic.0.severity = Severity::Warning;
+        diagnostic
+    }
+
+    pub fn code(mut self, code: &'static str) -> Diagnostic {
+        self.0.code = Some(code);
+        self
+    }
+
+    pub fn help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.0.help = Some(help.into());
+        self
+    }
+
+    /// Point to the file, if no file has been given yet.
+    pub fn in_file(mut self, file: &str) -> Diagnostic {
+        if self.0.file.is_none() {
+            self.0.file = Some(file.to_owned());
+        }
+        self
+    }
+
+    /// Point to a line of the file, if no line has been given yet. `line` starts from 1.
+    pub fn at_line(mut self, line: usize, source: &str) -> Diagnostic {
+        if self.0.line.is_none() {
+            self.0.line = Some(line);
+            self.0.source = Some(source.to_owned());
+        }
+        self
+    }
+
+    /// Underline `length` characters starting at `column`, if no column has been given yet.
+    /// `column` starts from 1.
+    pub fn at_column(mut self, column: usize, length: usize) -> Diagnostic {
+        if self.0.column.is_none() {
+            self.0.column = Some(column);
+            self.0.length = length.max(1);
+        }
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.0.severity == Severity::Error
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.0.severity
+    }
+
+    pub fn code_str(&self) -> Option<&'static str> {
+        self.0.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    /// File, line and column, for sorting
+    pub fn location(&self) -> (Option<&str>, Option<usize>, Option<usize>) {
+        (self.0.file.as_deref(), self.0.line, self.0.column)
+    }
+}
+
+impl Diagnostic {
+    /// Print to stderr, in color if it is a terminal.
+    pub fn eprint(&self) {
+        match io::stderr().is_terminal() {
+            true => eprintln!("{:#}", self),
+            false => eprintln!("{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// Print in the style of rustc, underlining the offending part of the source line. The
+    /// alternate form, `{:#}`, is colored for a terminal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let d = &self.0;
+        let color = f.alternate();
+        // Wrap `text` in the ANSI escape `style` when coloring
+        let paint = |style: &str, text: &str| match color {
+            true => format!("\x1b[{}m{}\x1b[0m", style, text),
+            false => text.to_owned(),
+        };
+        let severity = match d.severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+        };
+        let name = match (d.severity, d.code) {
+            (Severity::Error, Some(c)) => format!("error[{}]", c),
+            (Severity::Warning, Some(c)) => format!("warning[{}]", c),
+            (Severity::Error, None) => "error".to_owned(),
+            (Severity::Warning, None) => "warning".to_owned(),
+        };
+        write!(f, "{}{}", paint(severity, &name), paint("1", &format!(": {}", d.message)))?;
+
+        let line_text = d.line.map(|l| l.to_string()).unwrap_or_default();
+        let pad = " ".repeat(line_text.len());
+        if let Some(file) = &d.file {
+            write!(f, "\n{}{} {}", pad, paint("1;34", "-->"), file)?;
+            if let Some(l) = d.line {
+                write!(f, ":{}", l)?;
+                if let Some(c) = d.column {
+                    write!(f, ":{}", c)?;
+                }
+            }
+        }
+        if let Some(source) = &d.source {
+            write!(f, "\n{} {}", pad, paint("1;34", "|"))?;
+            write!(f, "\n{} {}", paint("1;34", &format!("{} |", line_text)), source)?;
+            if let Some(c) = d.column {
+                // Tabs keep their width in the underline
+                let indent = source.chars().take(c - 1).map(|ch| if ch == '\t' { '\t' } else { ' ' }).collect::<String>();
+                write!(f, "\n{} {} {}{}", pad, paint("1;34", "|"), indent, paint(severity, &"^".repeat(d.length)))?;
+            }
+        }
+        if let Some(help) = &d.help {
+            write!(f, "\n{} {} {}: {}", pad, paint("1;34", "="), paint("1", "help"), help)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Diagnostic {
+        Diagnostic::error(message)
+    }
+}
+
+impl From<&str> for Diagnostic {
+    fn from(message: &str) -> Diagnostic {
+        Diagnostic::error(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_only_in_the_alternate_form() {
+        let d = Diagnostic::warning("Unused label").code(codes::UNUSED_LABEL).in_file("text/a.md").at_line(3, "~eq:x");
+        assert_eq!(d.to_string(), "warning[W002]: Unused label\n --> text/a.md:3\n  |\n3 | ~eq:x");
+        assert!(!d.to_string().contains('\x1b'));
+        assert!(format!("{:#}", d).starts_with("\x1b[1;33mwarning[W002]\x1b[0m\x1b[1m: Unused label\x1b[0m"));
+    }
+}
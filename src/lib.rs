@@ -0,0 +1,34 @@
+//! Wikid compiles a directory of markdown into a static HTML wiki. The command line tool is built
+//! on this library, which can also render wikid markdown inside other programs with
+//! [`render_str`] and [`render_file`], without needing a wiki on disk.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+mod root;
+mod constants;
+mod build;
+mod section;
+mod diagnostics;
+mod config;
+mod list;
+mod open;
+mod theme;
+
+pub use build::{BuildSettings, CheckSettings, RefMap, Label, LabelKind, RenderSettings, Rendered, render_str, render_file};
+pub use constants::MyResult;
+pub use diagnostics::{Diagnostic, Severity, codes};
+pub use root::{Root, InitSettings};
+pub use section::{AddSettings, NewSettings, RmSettings, MvSettings};
+pub use config::ConfigSettings;
+pub use list::ListSettings;
+pub use open::OpenSettings;
+pub use theme::ThemeSettings;
+
+/// Set by the --verbose flag of the command being run
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
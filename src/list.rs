@@ -0,0 +1,162 @@
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use crate::constants::*;
+use crate::section::Section;
+use crate::build::{Context, Node, Report};
+
+#[derive(Parser)]
+pub struct ListSettings {
+    #[command(subcommand)]
+    command: ListCommand,
+    /// Only list what is inside this section, such as physics
+    #[arg(long, global = true)]
+    section: Option<String>,
+    /// Print JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum ListCommand {
+    /// Print the tree of sections
+    Sections,
+    /// Print the path and title of every page
+    Pages,
+    /// Print every label with its kind, number and where it is defined
+    Labels {
+        /// Only print labels of this kind
+        #[arg(long, value_parser = ["eq", "fig", "sec"])]
+        kind: Option<String>,
+    },
+}
+
+/// Print rows with their columns aligned
+fn print_table(rows: &[Vec<String>]) {
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let widths = (0..columns).map(|i| rows.iter().filter_map(|r| r.get(i)).map(|c| c.chars().count()).max().unwrap_or(0)).collect::<Vec<_>>();
+    for row in rows {
+        let mut line = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            match i + 1 == row.len() {
+                true => line.push_str(cell),
+                false => line.push_str(&format!("{:width$}  ", cell, width=widths[i])),
+            };
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+fn print_json(value: &Value) -> MyResult<()> {
+    match serde_json::to_string_pretty(value) {
+        Ok(t) => println!("{}", t),
+        Err(_) => return Err("Could not write the list as JSON".into())
+    };
+    Ok(())
+}
+
+impl ListSettings {
+    /// True if the file or directory at `local_path` is inside the section given with --section
+    fn in_section(&self, local_path: &str) -> bool {
+        match &self.section {
+            Some(s) => local_path.starts_with(&format!("text/{}/", s.trim_matches('/'))),
+            None => true,
+        }
+    }
+
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        let ctx = Context::load(chosen, false, true)?;
+        let tree = Node::new(&ctx)?;
+        if let Some(s) = &self.section {
+            let dir = format!("text/{}", s.trim_matches('/'));
+            if !tree.local_dirs().contains(&dir) {
+                return Err(format!("There is no section {}", s).into());
+            }
+        }
+        match &self.command {
+            ListCommand::Sections => {
+                let paths = tree.local_paths();
+                let mut sections = Vec::new();
+                for dir in tree.local_dirs() {
+                    if !self.in_section(&format!("{}/", dir)) {
+                        continue;
+                    }
+                    // Directories without settings hold images rather than pages
+                    if let Some(section) = Section::load(&ctx.root, &dir)? {
+                        let pages = paths.iter().filter(|p| p.starts_with(&format!("{}/", dir)) && !p.ends_with("_toc.md")).count();
+                        sections.push((dir, section, pages));
+                    }
+                }
+                if self.json {
+                    return print_json(&Value::Array(sections.iter().map(|(dir, s, pages)| json!({
+                        "path": &dir[5..],
+                        "color": s.color,
+                        "draft": s.draft,
+                        "ignore": s.ignore,
+                        "pages": pages,
+                    })).collect()));
+                }
+                print_table(&sections.iter().map(|(dir, s, pages)| {
+                    let depth = dir.matches('/').count() - 1;
+                    let mut flags = Vec::new();
+                    if s.draft {
+                        flags.push("draft");
+                    }
+                    if s.ignore {
+                        flags.push("ignored");
+                    }
+                    let color = match s.color.is_empty() {
+                        true => "inherited".to_owned(),
+                        false => s.color.clone(),
+                    };
+                    vec![format!("{}{}", "  ".repeat(depth), s.name), color, format!("{} {}", pages, if *pages == 1 { "page" } else { "pages" }), flags.join(", ")]
+                }).collect::<Vec<_>>());
+            },
+            ListCommand::Pages => {
+                let mut report = Report::new();
+                let pages = tree.pages(&ctx, &mut report).into_iter().filter(|p| self.in_section(&p.local_path)).collect::<Vec<_>>();
+                report.print();
+                if self.json {
+                    return print_json(&Value::Array(pages.iter().map(|p| json!({
+                        "path": p.local_path,
+                        "title": p.title,
+                        "date": p.date.format("%Y-%m-%d").to_string(),
+                        "draft": p.draft,
+                    })).collect()));
+                }
+                print_table(&pages.iter().map(|p| vec![
+                    p.local_path.clone(),
+                    p.date.format("%Y-%m-%d").to_string(),
+                    match p.draft {
+                        true => format!("{} (draft)", p.title),
+                        false => p.title.clone(),
+                    },
+                ]).collect::<Vec<_>>());
+            },
+            ListCommand::Labels { kind } => {
+                // Problems with the labels are reported by wikid check
+                let mut report = Report::new();
+                let pages = tree.pages(&ctx, &mut report);
+                let labels = tree.ref_map(&pages, &ctx, &mut report)?.labels().into_iter()
+                    .filter(|l| self.in_section(&l.local_path))
+                    .filter(|l| kind.as_ref().is_none_or(|k| k == l.kind.name()))
+                    .collect::<Vec<_>>();
+                if self.json {
+                    return print_json(&Value::Array(labels.iter().map(|l| json!({
+                        "label": l.label,
+                        "kind": l.kind.name(),
+                        "number": l.number,
+                        "path": l.local_path,
+                        "line": l.line,
+                    })).collect()));
+                }
+                print_table(&labels.iter().map(|l| vec![
+                    l.label.clone(),
+                    l.kind.name().to_owned(),
+                    l.number.clone(),
+                    format!("{}:{}", l.local_path, l.line),
+                ]).collect::<Vec<_>>());
+            },
+        };
+        Ok(())
+    }
+}
@@ -5,18 +5,7 @@
 // to generate arguments dynamically.
 use clap::{Parser, Subcommand};
 
-mod root;
-mod constants;
-mod build;
-mod section;
-use build::BuildSettings;
-use root::InitSettings;
-use section::AddSettings;
-
-static mut VERBOSE: bool = false;
-fn is_verbose() -> bool {
-    unsafe {VERBOSE}
-}
+use wikid::{BuildSettings, CheckSettings, InitSettings, AddSettings, NewSettings, RmSettings, MvSettings, ConfigSettings, ListSettings, OpenSettings, ThemeSettings};
 
 #[derive(Subcommand)]
 enum Commands {
@@ -24,12 +13,24 @@ enum Commands {
     Init(InitSettings),
     /// Compile markdown to HTML
     Build(BuildSettings),
-    /// Open the blog locally with Chrome
-    Open,
+    /// Check the wiki for broken links and other problems without building it
+    Check(CheckSettings),
+    /// Open the wiki, or one of its pages or labels, in a browser
+    Open(OpenSettings),
     /// Add a section
     Add(AddSettings),
-    // Rm(RmSettings),
-    // Mv(MvSettings),
+    /// Get, set or list the settings of the wiki and its sections
+    Config(ConfigSettings),
+    /// List the sections, pages or labels of the wiki
+    List(ListSettings),
+    /// List or install themes
+    Theme(ThemeSettings),
+    /// Create a page from a template and add it to its section's table of contents
+    New(NewSettings),
+    /// Remove a section or page
+    Rm(RmSettings),
+    /// Move or rename a section or page, rewriting the links to it
+    Mv(MvSettings),
     // Root(RootSettings),
     Syntax,
 }
@@ -40,38 +41,46 @@ enum Commands {
 struct Cli {
     #[command(subcommand)]
     commands: Commands,
+    /// Directory of the wiki. Defaults to $WIKID_ROOT, or else the nearest directory containing
+    /// .wikid above the current one
+    #[arg(long, global = true)]
+    root: Option<String>,
 }
 
 fn main() {
     let cli = Cli::parse();
+    let root = cli.root.or_else(|| std::env::var("WIKID_ROOT").ok());
+    let root = root.as_deref();
 
     let result = match cli.commands {
-        Commands::Init(m) => m.run(),
-        Commands::Open => {
-            std::process::Command::new("/Applications/Google Chrome.app/Contents/MacOS/Google Chrome")
-                .arg("--allow-file-access-from-files")
-                .arg(&root::Root::get_path_from_local("html/index.html").unwrap())
-                .spawn().unwrap();
-            Ok(())
-        }
-        Commands::Build(m) => m.run(),
-        Commands::Add(m) => m.run(),
+        Commands::Init(m) => m.run(root),
+        Commands::Open(m) => m.run(root),
+        Commands::Build(m) => m.run(root),
+        Commands::Check(m) => m.run(root),
+        Commands::Add(m) => m.run(root),
+        Commands::Config(m) => m.run(root),
+        Commands::List(m) => m.run(root),
+        Commands::Theme(m) => m.run(root),
         Commands::Syntax => {
             display_syntax();
             Ok(())
         },
-        // Commands::Rm(m) => m.run(),
-        // Commands::Mv(m) => m.run(),
+        Commands::New(m) => m.run(root),
+        Commands::Rm(m) => m.run(root),
+        Commands::Mv(m) => m.run(root),
     };
 
     match result {
         Ok(_) => (),
-        Err(e) => println!("{}", e),
+        Err(e) => {
+            e.eprint();
+            std::process::exit(1);
+        }
     };
 }
 
 fn display_syntax() {
-    const HELP_STR: &'static str = "\x1b[1;32mWikid syntax\x1b[0m
+    const HELP_STR: &str = "\x1b[1;32mWikid syntax\x1b[0m
 \x1b[1;36mText\x1b[0m
 -  _italics_
 -  *bold*
@@ -84,6 +93,14 @@ fn display_syntax() {
 -  [footnote]
 -  For all links, {} represent a local path and [] represent a global path
 
+\x1b[1;36mFront matter\x1b[0m
+-  Begin a page with YAML between two --- lines, or TOML between two +++ lines
+-  Fields are title, date (YYYY-MM-DD), tags, summary, draft, author, and any custom fields
+
+\x1b[1;36mTables of contents\x1b[0m
+-  An empty _toc.md lists every page in its section
+-  %index [title|date|length|path], to insert the list of pages. Prefix the key with - to reverse
+
 \x1b[1;36mLaTeX\x1b[0m
 -  ~label, before the equation
 -  Reference equations with []{equation label}
@@ -0,0 +1,235 @@
+use clap::Parser;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use crate::constants::*;
+use crate::root::Root;
+use crate::build::{BuildSettings, Context, Node, Report};
+
+#[derive(Parser)]
+pub struct OpenSettings {
+    /// Page or label to open, such as physics/spin or eq:schrodinger. Opens the home page if not
+    /// given
+    target: Option<String>,
+    /// Build the wiki and serve it at http://localhost instead of opening the files directly.
+    /// Needed for pages with applets, which browsers will not load from files
+    #[arg(long)]
+    serve: bool,
+    /// Port of the preview server
+    #[arg(long, default_value_t = 8000)]
+    port: u16,
+    /// Verbosity
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// Open `link` with the browser set in wikid.json, or else $BROWSER, or else the system's default
+pub(crate) fn open_link(link: &str, root: &Root) -> MyResult<()> {
+    // $BROWSER may list several browsers to try in turn
+    let browsers = root.browser.clone()
+        .or_else(|| std::env::var("BROWSER").ok())
+        .map(|b| b.split(':').map(|b| b.trim().to_owned()).filter(|b| !b.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if browsers.is_empty() {
+        if open::that(link).is_err() {
+            return Err(format!("Could not open {}. Set the browser with wikid config set browser <name>", link).into());
+        }
+        return Ok(());
+    }
+    for browser in &browsers {
+        if open::with(link, browser).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(format!("Could not open {} with {}", link, browsers.join(" or ")).into())
+}
+
+/// The type of a file served by the preview server
+fn content_type(path: &str) -> &'static str {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("wasm") => "application/wasm",
+        Some("xml") => "application/xml; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Undo the escaping of a URL path
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i+1..i+3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            },
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The file inside `site_dir`, which is canonical, that a request for `path` is answered with.
+/// None if there is no such file, or if the path leads outside `site_dir`.
+fn site_file(site_dir: &Path, path: &str) -> Option<PathBuf> {
+    // Never serve hidden files
+    if path.split('/').any(|p| p.starts_with('.')) {
+        return None;
+    }
+    let mut file_path = site_dir.join(path.trim_start_matches('/'));
+    if path.ends_with('/') || path.is_empty() {
+        file_path.push("index.html");
+    }
+    match file_path.canonicalize() {
+        Ok(p) if p.starts_with(site_dir) && p.is_file() => Some(p),
+        _ => None,
+    }
+}
+
+/// Answer one request for a file inside the built site `site_dir`, which is canonical
+fn respond(mut stream: TcpStream, site_dir: &Path) {
+    let mut request = String::new();
+    if BufReader::new(&stream).read_line(&mut request).is_err() {
+        return;
+    }
+    let mut parts = request.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return,
+    };
+    let path = percent_decode(target.split(['?', '#']).next().unwrap_or("/"));
+    let file = site_file(site_dir, &path).filter(|_| method == "GET");
+    let (status, body, kind) = match file.and_then(|f| fs::read(&f).ok().map(|b| (b, f))) {
+        Some((b, f)) => ("200 OK", b, content_type(&f.to_string_lossy())),
+        None => ("404 Not Found", b"Not found".to_vec(), "text/plain; charset=utf-8"),
+    };
+    if crate::is_verbose() {
+        println!("{} {} {}", method, path, status);
+    }
+    let header = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, kind, body.len());
+    let _ = stream.write_all(header.as_bytes()).and_then(|_| stream.write_all(&body));
+}
+
+/// The temporary directory a preview is built into, removed when the preview stops
+struct PreviewDir(PathBuf);
+
+impl Drop for PreviewDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+impl OpenSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let mut ctx = Context::load(chosen, false, false)?;
+        let listener = match self.serve {
+            true => {
+                let listener = match TcpListener::bind(("127.0.0.1", self.port)) {
+                    Ok(l) => l,
+                    Err(_) => return Err(format!("Could not start the preview server on port {}", self.port).into())
+                };
+                // Links point to the server rather than to the files, so the preview is built
+                // outside html/
+                let local_url = format!("http://localhost:{}", self.port);
+                ctx.root.local_url = Some(local_url.clone());
+                let preview = PreviewDir(std::env::temp_dir().join(format!("wikid-preview-{}", std::process::id())));
+                // Ctrl+C ends the process without unwinding, so the preview is removed here too
+                let dir = preview.0.clone();
+                if ctrlc::set_handler(move || {
+                    let _ = fs::remove_dir_all(&dir);
+                    std::process::exit(130);
+                }).is_err() {
+                    return Err("Could not listen for Ctrl+C".into());
+                }
+                BuildSettings::preview(self.verbose, format!("{}/", preview.0.display()), local_url).run(Some(&ctx.root.dir))?;
+                let site_dir = match preview.0.canonicalize() {
+                    Ok(d) => d,
+                    Err(_) => return Err(format!("Could not find the preview at {}", preview.0.display()).into())
+                };
+                Some((listener, site_dir, preview))
+            },
+            false => None,
+        };
+
+        if listener.is_none() && !Path::new(&ctx.path("html/index.html")).exists() {
+            return Err("The wiki has not been built yet. Build it with wikid build".into());
+        }
+        let link = match &self.target {
+            Some(target) => {
+                let tree = Node::new(&ctx)?;
+                let pages = tree.pages(&ctx, &mut Report::new());
+                ctx.ref_map = tree.ref_map(&pages, &ctx, &mut Report::new())?;
+                match ctx.ref_map.get_link(target.trim_end_matches(".md"), None) {
+                    Some((_, link)) => link,
+                    None => return Err(format!("Could not find a page or label {}", target).into())
+                }
+            },
+            None => ctx.link("html/index.html")?,
+        };
+        open_link(&link, &ctx.root)?;
+
+        if let Some((listener, site_dir, _preview)) = listener {
+            println!("Serving the wiki at http://localhost:{}/index.html from {}. Press Ctrl+C to stop", self.port, site_dir.display());
+            // One thread per connection, so that a slow request does not hold up the rest
+            for stream in listener.incoming().flatten() {
+                let site_dir = site_dir.clone();
+                thread::spawn(move || respond(stream, &site_dir));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn percent_decode_unescapes() {
+        assert_eq!(percent_decode("/a%20page.html"), "/a page.html");
+        assert_eq!(percent_decode("/%C3%A9t%C3%A9"), "/été");
+        assert_eq!(percent_decode("/%2e%2E/x"), "/../x");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%zz"), "/%zz");
+    }
+
+    #[test]
+    fn site_file_stays_inside_the_site() {
+        let dir = std::env::temp_dir().join(format!("wikid-site-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("site/physics")).unwrap();
+        fs::write(dir.join("site/index.html"), "").unwrap();
+        fs::write(dir.join("site/physics/spin.html"), "").unwrap();
+        fs::write(dir.join("site/.hidden"), "").unwrap();
+        fs::write(dir.join("secret.txt"), "").unwrap();
+        let site = dir.join("site").canonicalize().unwrap();
+
+        assert_eq!(site_file(&site, "/"), Some(site.join("index.html")));
+        assert_eq!(site_file(&site, "/physics/spin.html"), Some(site.join("physics/spin.html")));
+        assert_eq!(site_file(&site, "/../secret.txt"), None);
+        assert_eq!(site_file(&site, "/physics/../../secret.txt"), None);
+        assert_eq!(site_file(&site, "/.hidden"), None);
+        assert_eq!(site_file(&site, "/physics"), None);
+        assert_eq!(site_file(&site, "/missing.html"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
 use std::{fs, env};
 use clap::Parser;
 use serde::{Serialize, Deserialize};
@@ -6,9 +7,19 @@ use std::fs::File;
 use std::io::*;
 use crate::constants::*;
 use crate::section::Section;
-use git2::Repository;
+use git2::{Repository, Sort};
+use chrono::{DateTime, Utc};
 
-const DEFAULT_COLOR: &'static str = "#cccccc";
+const DEFAULT_COLOR: &str = "#cccccc";
+
+/// Dates read from the git history of the wiki
+#[derive(Debug)]
+struct GitDates {
+    /// Time the wiki was last updated
+    updated: DateTime<Utc>,
+    /// Time of the latest commit changing each file, by path from the root directory
+    files: HashMap<String, DateTime<Utc>>,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Root {
@@ -16,11 +27,57 @@ pub struct Root {
     pub wikid_version_minor: String,
     pub name: String,
     pub public_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub bg_image: Option<String>,
+    /// How the background image fills the page, one of BG_FITS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_fit: Option<String>,
+    /// Percentage of the background color laid over the background image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_dim: Option<u32>,
+    /// Radius in pixels of the blur applied to the background image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_blur: Option<u32>,
+    /// Color drawn beneath the background image, instead of the theme's background color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub fonts: Option<Vec<String>>,
     pub main_color: String,
-    pub dark_mode: bool,
+    /// Theme of every page, or None to follow the reader's system preference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark_mode: Option<bool>,
     pub font_size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_sort: Option<String>,
+    #[serde(default)]
+    pub rss: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub robots: Option<String>,
+    /// Browser to open pages with, instead of $BROWSER or the system's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub browser: Option<String>,
+    /// Description of the wiki, used for pages without a summary and in feeds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Name of the theme the stylesheets are made from, if not the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Path of a CSS file from the wiki directory, appended to every stylesheet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_css: Option<String>,
+    /// Old address of each moved page and the address it moved to, both inside html/
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redirects: BTreeMap<String, String>,
+    /// Directory of the wiki, set when it is loaded
+    #[serde(skip)]
+    pub dir: String,
+    /// Address local links into html/ point to instead of the files, set while serving a preview
+    #[serde(skip)]
+    pub local_url: Option<String>,
+    /// Read the first time a date is needed, so that the history is only walked once
+    #[serde(skip)]
+    dates: OnceLock<GitDates>,
 }
 
 #[derive(Parser)]
@@ -36,35 +93,69 @@ pub struct InitSettings {
 }
 
 impl Root {
-    fn new(name: String) -> Root {
+    fn new(name: String, dir: String) -> Root {
         Root { wikid_version_major: env!("CARGO_PKG_VERSION_MAJOR").to_owned(),
             wikid_version_minor: env!("CARGO_PKG_VERSION_MINOR").to_owned(),
             name,
             public_url: String::new(),
             bg_image: None,
+            bg_fit: None,
+            bg_dim: None,
+            bg_blur: None,
+            bg_color: None,
             fonts: None,
-            dark_mode: true,
+            dark_mode: None,
             font_size: 16,
             main_color: DEFAULT_COLOR.to_owned(),
+            index_sort: None,
+            rss: false,
+            robots: None,
+            description: None,
+            browser: None,
+            theme: None,
+            custom_css: None,
+            redirects: BTreeMap::new(),
+            dir,
+            local_url: None,
+            dates: OnceLock::new(),
         }
     }
 
-    pub fn get_root_dir() -> MyResult<String> {
+    /// The directory of the wiki: `chosen` if given, such as with --root or WIKID_ROOT, otherwise
+    /// the nearest directory containing .wikid above the current one. Relative paths are taken
+    /// from the current directory.
+    pub fn find_dir(chosen: Option<&str>) -> MyResult<String> {
+        let mut path = match env::current_dir() {
+            Ok(d) => d,
+            Err(_) => return Err("Could not read the current directory".into())
+        };
+        if let Some(dir) = chosen {
+            path.push(dir);
+            if !path.join(".wikid").exists() {
+                return Err(format!("No wiki was found at {}", dir).into());
+            }
+            return match path.to_str() {
+                Some(p) => Ok(p.trim_end_matches('/').to_owned()),
+                None => Err(format!("The path {} is not valid unicode", dir).into())
+            };
+        }
+
         // Scan for the wikid file.
-        let mut path = env::current_dir().unwrap();
-        
         loop {
             // Check whether wikid directory exists
             path.push(".wikid");
             if path.exists() {
                 path.pop();
-                break Ok(path.as_os_str().to_str().expect("Path was corrupted").to_owned())
+                break match path.to_str() {
+                    Some(p) => Ok(p.to_owned()),
+                    None => Err("The path of the wiki is not valid unicode".into())
+                };
             }
             path.pop();
 
             // Advance
             if !path.pop() {
-                break Err("Not initialized in wikid directory".to_owned());
+                break Err("Not initialized in wikid directory".into());
             };
         }
     }
@@ -92,18 +183,24 @@ impl Root {
         }
         match public {
             true => Ok(format!("{}/{}", self.public_url, no_space_local)),
-            false => Ok(format!("file://{}/{}", Self::get_root_dir()?, no_space_local))
+            false => match (&self.local_url, no_space_local.strip_prefix("html/")) {
+                (Some(url), Some(site_path)) => Ok(format!("{}/{}", url.trim_end_matches('/'), site_path)),
+                _ => Ok(format!("file://{}/{}", self.dir, no_space_local))
+            }
         }
     }
 
-    pub fn get_path_from_local(local_dir: &str) -> MyResult<String> {
-        Ok(format!("{}/{}", Self::get_root_dir()?, local_dir))
+    /// Absolute path of a file given its path from the wiki directory
+    pub fn path(&self, local_dir: &str) -> String {
+        format!("{}/{}", self.dir, local_dir)
     }
 
-    pub fn summon() -> MyResult<Root> {
-        let paths = match fs::read_dir(Root::get_path_from_local(".wikid")?) {
+    /// Load wikid.json from the directory found by `find_dir(chosen)`
+    pub fn summon(chosen: Option<&str>) -> MyResult<Root> {
+        let dir = Root::find_dir(chosen)?;
+        let paths = match fs::read_dir(format!("{}/.wikid", dir)) {
             Ok(p) => p,
-            Err(_) => return Err("Wikid directory was invalid".to_owned())
+            Err(_) => return Err("Wikid directory was invalid".into())
         };
 
         for test_path in paths {
@@ -116,80 +213,142 @@ impl Root {
                     if n == "wikid.json" {
                         let root_str = match fs::read_to_string(path) {
                             Ok(s) => s,
-                            Err(_) => return Err("Could not open wikid.json".to_owned())
+                            Err(_) => return Err("Could not open wikid.json".into())
                         };
-                        return Ok(match serde_json::from_str(&root_str) {
+                        let mut root: Root = match serde_json::from_str(&root_str) {
                             Ok(r) => r,
-                            Err(e) => return Err(format!("Wikid.json was corrupted ({})", e))
-                        })
+                            Err(e) => return Err(format!("Wikid.json was corrupted ({})", e).into())
+                        };
+                        root.dir = dir;
+                        return Ok(root);
                     }
                 }
             }
         }
-        Err("Could not find the wikid.json file.".to_owned())
+        Err("Could not find the wikid.json file.".into())
     }
 
     pub fn write(&self) -> MyResult<()> {
         let json_text = match serde_json::to_string_pretty(self) {
-            Err(_) => return Err("Failed to write root data to json".to_owned()),
+            Err(_) => return Err("Failed to write root data to json".into()),
             Ok(t) => t
         };
 
-        let mut file = match File::create(Root::get_path_from_local(".wikid/wikid.json")?) {
+        let mut file = match File::create(self.path(".wikid/wikid.json")) {
             Ok(f) => f,
-            Err(_) => return Err("Could not create wikid.json".to_owned())
+            Err(_) => return Err("Could not create wikid.json".into())
         };
-        if let Err(_) = file.write_all(json_text.as_bytes()) {
-            return Err("Could not write to wikid.json".to_owned());
+        if file.write_all(json_text.as_bytes()).is_err() {
+            return Err("Could not write to wikid.json".into());
         }
 
         Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn get_github_url() -> MyResult<String> {
-        let repo = match Repository::open(Root::get_root_dir()?) {
+    pub fn get_github_url(&self) -> MyResult<String> {
+        let repo = match Repository::open(&self.dir) {
             Ok(r) => r,
-            Err(_) => return Err("Could not find repo in this wiki".to_owned())
+            Err(_) => return Err("Could not find repo in this wiki".into())
         };
         let origin = match repo.find_remote("origin") {
             Ok(o) => o,
-            Err(_) => return Err("Could not find remote named origin".to_owned())
+            Err(_) => return Err("Could not find remote named origin".into())
         };
         Ok(match origin.url() {
             Some(u) => u,
-            None => return Err("Origin did not have a url".to_owned())
+            None => return Err("Origin did not have a url".into())
         }.to_string())
     }
 
-    /// Returns the name of the current section
-    pub fn get_section(&self, path: &str) -> String {
-        let path = Path::new(path);
-        path.parent().unwrap().file_name().unwrap().to_str().unwrap().to_owned()
+    /// Returns every section, each before the sections inside it, in alphabetical order
+    pub fn get_sections(&self) -> MyResult<Vec<Section>> {
+        let mut sections = Vec::new();
+        self.get_sections_in_dir("text", &mut sections)?;
+        Ok(sections)
     }
 
-    /// Returns the names of all sections
-    pub fn get_sections(&self) -> Vec<Section> {
-        let mut sections = Vec::new();
-        self.get_sections_in_dir(Path::new(&Self::get_root_dir().unwrap()), &mut sections);
-        sections
+    /// Adds the sections inside the directory `local_dir`, which starts with text/
+    pub fn get_sections_in_dir(&self, local_dir: &str, sections: &mut Vec<Section>) -> MyResult<()> {
+        let mut paths = match fs::read_dir(self.path(local_dir)) {
+            Ok(p) => p.filter_map(|p| p.ok()).map(|p| p.path()).filter(|p| p.is_dir()).collect::<Vec<_>>(),
+            Err(_) => return Err(format!("Could not open path {}", local_dir).into())
+        };
+        paths.sort();
+        for path in paths {
+            let name = path.file_name().expect("Incorrectly formatted path").to_str().expect("Incorrectly formatted path");
+            let child = format!("{}/{}", local_dir, name);
+            if let Some(section) = Section::load(self, &child)? {
+                sections.push(section);
+            }
+            self.get_sections_in_dir(&child, sections)?;
+        }
+        Ok(())
     }
 
-    /// Returns the sections of all sections
-    pub fn get_sections_in_dir(&self, path: &Path, sections: &mut Vec<Section>) {
-        for file in fs::read_dir(path).unwrap() {
-            let file_path = file.unwrap().path();
-            if fs::metadata(&file_path).unwrap().is_dir() {
-                self.get_sections_in_dir(&file_path, sections);
-            } else {
-                if file_path.file_name().unwrap() == ".wikid.json" {
-                    let text = fs::read_to_string(&file_path).unwrap();
-                    let save_section = serde_json::from_str(&text).unwrap();
-                    let name = file_path.parent().unwrap().file_name().unwrap().to_str().to_owned().unwrap();
-                    sections.push(Section::from_save_section(save_section, name))
+    /// The time the wiki was last updated: `SOURCE_DATE_EPOCH` if set, otherwise the time of the
+    /// latest git commit, or the time of the build if nothing has been committed
+    pub fn get_updated_date(&self) -> DateTime<Utc> {
+        self.get_git_dates().updated
+    }
+
+    /// The time of the latest git commit changing the file at `local_path`, capped at
+    /// `SOURCE_DATE_EPOCH`, or the time the wiki was last updated if the file is not committed
+    pub fn get_file_date(&self, local_path: &str) -> DateTime<Utc> {
+        let dates = self.get_git_dates();
+        match dates.files.get(local_path) {
+            Some(d) => *d.min(&dates.updated),
+            None => dates.updated,
+        }
+    }
+
+    fn get_git_dates(&self) -> &GitDates {
+        self.dates.get_or_init(|| {
+            let repo = Repository::open(&self.dir).ok();
+            let head = repo.as_ref()
+                .and_then(|r| r.head().ok()?.peel_to_commit().ok())
+                .and_then(|c| DateTime::from_timestamp(c.time().seconds(), 0));
+            GitDates {
+                updated: Root::get_source_date_epoch().or(head).unwrap_or_else(Utc::now),
+                files: repo.map(|r| Root::get_commit_dates(&r)).unwrap_or_default(),
+            }
+        })
+    }
+
+    /// Walk the history from the newest commit, recording the first time each file is changed
+    fn get_commit_dates(repo: &Repository) -> HashMap<String, DateTime<Utc>> {
+        let mut dates = HashMap::new();
+        let mut walk = match repo.revwalk() {
+            Ok(w) => w,
+            Err(_) => return dates
+        };
+        if walk.push_head().is_err() || walk.set_sorting(Sort::TIME).is_err() {
+            return dates;// No commits yet
+        }
+        for commit in walk.filter_map(|oid| repo.find_commit(oid.ok()?).ok()) {
+            let time = match DateTime::from_timestamp(commit.time().seconds(), 0) {
+                Some(t) => t,
+                None => continue
+            };
+            let tree = commit.tree().ok();
+            let parent_tree = commit.parent(0).ok().and_then(|p| p.tree().ok());
+            let diff = match repo.diff_tree_to_tree(parent_tree.as_ref(), tree.as_ref(), None) {
+                Ok(d) => d,
+                Err(_) => continue
+            };
+            for delta in diff.deltas() {
+                if let Some(path) = delta.new_file().path().and_then(|p| p.to_str()) {
+                    dates.entry(path.to_owned()).or_insert(time);
                 }
             }
         }
+        dates
+    }
+
+    /// The time given by the `SOURCE_DATE_EPOCH` environment variable, if set
+    pub fn get_source_date_epoch() -> Option<DateTime<Utc>> {
+        let epoch = env::var("SOURCE_DATE_EPOCH").ok()?;
+        DateTime::from_timestamp(epoch.trim().parse::<i64>().ok()?, 0)
     }
 
     // pub fn rename(&mut self, matches: &ArgMatches) -> MyResult<()> {
@@ -200,37 +359,40 @@ impl Root {
 }
 
 impl InitSettings {
-    pub fn run(&self) -> MyResult<()> {
-        unsafe {
-            crate::VERBOSE = self.verbose;
+    /// Create the wiki in the directory `chosen`, or else the current directory
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let dir = chosen.unwrap_or(".");
+        if fs::create_dir_all(dir).is_err() {
+            return Err(format!("Could not create directory {}", dir).into());
         }
-        if let Err(_) = fs::create_dir(".wikid") {
-            return Err("Could not create .wikid directory".to_owned());
+        if fs::create_dir(format!("{}/.wikid", dir)).is_err() {
+            return Err("Could not create .wikid directory".into());
         }
 
-        if let Err(_) = fs::create_dir("code") {
-            return Err("Could not create code directory".to_owned());
+        if fs::create_dir(format!("{}/code", dir)).is_err() {
+            return Err("Could not create code directory".into());
         }
 
-        if let Err(_) = fs::create_dir("html") {
-            return Err("Could not create target directory".to_owned());
+        if fs::create_dir(format!("{}/html", dir)).is_err() {
+            return Err("Could not create target directory".into());
         }
 
-        if let Err(_) = fs::create_dir("text") {
-            return Err("Could not create target directory".to_owned());
+        if fs::create_dir(format!("{}/text", dir)).is_err() {
+            return Err("Could not create target directory".into());
         }
 
-        if let Err(_) = File::create("text/_toc.md") {
-            return Err("Could not create table of contents".to_owned());
+        if File::create(format!("{}/text/_toc.md", dir)).is_err() {
+            return Err("Could not create table of contents".into());
         }
 
-        if let Err(_) = fs::write(".gitignore", "text/\n.wikid/\n.gitignore".as_bytes()) {
-            return Err("Could not create .gitignore".to_owned())
+        if fs::write(format!("{}/.gitignore", dir), ".wikid/\n.gitignore".as_bytes()).is_err() {
+            return Err("Could not create .gitignore".into())
         }
 
         if !self.nogit {
-            if let Err(_) = Repository::init(".") {
-                return Err("Could not initialize github repository in this directory".to_owned());
+            if Repository::init(dir).is_err() {
+                return Err("Could not initialize github repository in this directory".into());
             }
         }
         else {
@@ -238,7 +400,7 @@ impl InitSettings {
         }
         println!("Created wiki {}", self.name);
 
-        let root = Root::new(self.name.clone());
+        let root = Root::new(self.name.clone(), dir.to_owned());
 
         root.write()
     }
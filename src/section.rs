@@ -2,17 +2,24 @@ use clap::Parser;
 use serde::{Serialize, Deserialize};
 use std::str::FromStr;
 use std::io::Write;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
+use std::path::Path;
 use crate::constants::*;
 use crate::root::Root;
+use crate::diagnostics::{Diagnostic, codes};
+use crate::build::{Context, Node, Page, Report, find_link_targets, target_keys, html_path};
 
 #[derive(Parser)]
 pub struct AddSettings {
-    /// Name of the section to add
+    /// Section to add, as a path inside text/ such as physics or physics/qm
     name: String,
-    /// Color of the section
+    /// Color of the section. Taken from the containing section or the wiki if not given
     #[arg(long)]
-    color: String, 
+    color: Option<String>,
+    /// Mark the section as a draft, which is left out of public builds
+    #[arg(long)]
+    draft: bool,
     /// Verbosity
     #[arg(short, long)]
     verbose: bool,
@@ -20,104 +27,220 @@ pub struct AddSettings {
 
 #[derive(Parser)]
 pub struct RmSettings {
-    /// Name of the section to remove
+    /// Section or page to remove, as a path inside text/ such as physics or physics/spin
     name: String,
-    /// Set to true to force removal
+    /// Remove a section even if it still contains pages
     #[arg(long)]
     force: bool,
+    /// Verbosity
+    #[arg(short, long)]
+    verbose: bool,
 }
 
 #[derive(Parser)]
-pub struct MvSettings {
-    /// Name of the section to move
+pub struct NewSettings {
+    /// Page to create, as a path inside text/ such as physics/spin
     name: String,
+    /// Title of the page. Taken from the file name if not given
+    #[arg(long)]
+    title: Option<String>,
+    /// Open the page in $EDITOR once it is created
+    #[arg(short, long)]
+    edit: bool,
+    /// Verbosity
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Parser)]
+pub struct MvSettings {
+    /// Section or page to move, as a path inside text/ such as physics or physics/spin
+    from: String,
+    /// New path inside text/
+    to: String,
+    /// Leave a page at each old address which sends readers to the new one
+    #[arg(long)]
+    redirect: bool,
+    /// Verbosity
+    #[arg(short, long)]
+    verbose: bool,
 }
 
 #[derive(Debug)]
 pub struct Section {
     pub name: String,
+    /// Path of the section's directory, starting with text/
+    pub path: String,
+    /// Empty to inherit the color of the containing section, or the main color of the wiki
     pub color: String,
     pub ignore: bool,
+    pub draft: bool,
+    /// Settings of wikid.json which this section and the sections inside it override
+    pub overrides: Overrides,
+}
+
+/// Settings of wikid.json which a section can override. Unset settings are inherited from the
+/// section containing it, or from wikid.json.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Overrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark_mode: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fonts: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_fit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_dim: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_blur: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Order of the generated index of the section's pages, which is the section's navigation
+    /// order. A hand-written table of contents keeps its own order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_sort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Appended after the custom CSS of wikid.json and of the sections containing this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_css: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SaveSection {
+    #[serde(default)]
     pub color: String,
     pub ignore: bool,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(flatten)]
+    pub overrides: Overrides,
 }
 
 impl Section {
-    pub fn new(name: String, color: Color) -> MyResult<Section> {
-        if let Err(_) = fs::create_dir(&name) {
-            return Err("Could not create section directory.".to_owned());
+    /// Create the section in the directory `local_dir`, which starts with text/. An empty color
+    /// is inherited.
+    pub fn new(root: &Root, local_dir: &str, color: String, draft: bool) -> MyResult<Section> {
+        let global_dir = root.path(local_dir);
+        if fs::create_dir(&global_dir).is_err() {
+            return Err("Could not create section directory.".into());
         }
-        if let Err(_) = File::create(format!("{}/_toc.md", name)) {
-            return Err("Could not create section table of contents.".to_owned());
+        if File::create(format!("{}/_toc.md", global_dir)).is_err() {
+            return Err("Could not create section table of contents.".into());
         }
 
         let out = Section {
-            name: name.to_owned(),
-            color: color.to_string(),
-            ignore: false
+            name: Path::new(local_dir).file_name().and_then(|n| n.to_str()).expect("Incorrectly formatted path").to_owned(),
+            path: local_dir.to_owned(),
+            color,
+            ignore: false,
+            draft,
+            overrides: Overrides::default(),
         };
 
-        // Save
-        let json_text = match serde_json::to_string(&out.save_section()) {
-            Err(_) => return Err("Failed to write root data to json".to_owned()),
+        out.write(root, local_dir)?;
+
+        Ok(out)
+    }
+
+    /// Save the settings of the section in the directory `local_dir`.
+    pub fn write(&self, root: &Root, local_dir: &str) -> MyResult<()> {
+        self.save(&root.path(&format!("{}/.wikid.json", local_dir)))
+    }
+
+    fn save(&self, path: &str) -> MyResult<()> {
+        let json_text = match serde_json::to_string(&self.save_section()) {
+            Err(_) => return Err("Failed to write root data to json".into()),
             Ok(t) => t
         };
-        let mut file = match File::create(format!("{}/.wikid.json", &name)) {
+        let mut file = match File::create(path) {
             Ok(f) => f,
-            Err(_) => return Err("Could not create wikid.json".to_owned())
+            Err(_) => return Err("Could not create wikid.json".into())
         };
-        if let Err(_) = file.write_all(json_text.as_bytes()) {
-            return Err("Could not write to wikid.json".to_owned());
+        if file.write_all(json_text.as_bytes()).is_err() {
+            return Err("Could not write to wikid.json".into());
         }
-
-        Ok(out)
+        Ok(())
     }
 
     fn save_section(&self) -> SaveSection {
         SaveSection {
             color: self.color.clone(),
             ignore: self.ignore,
+            draft: self.draft,
+            overrides: self.overrides.clone(),
         }
     }
 
-    pub(crate) fn from_save_section(sec: SaveSection, name: &str) -> Section {
+    pub(crate) fn from_save_section(sec: SaveSection, local_dir: &str) -> Section {
+        let name = Path::new(local_dir).file_name().expect("Incorrectly formatted path")
+            .to_str().expect("Incorrectly formatted path");
         Self {
             name: name.to_owned(),
+            path: local_dir.to_owned(),
             color: sec.color.clone(),
             ignore: sec.ignore,
+            draft: sec.draft,
+            overrides: sec.overrides,
         }
     }
+
+    /// Load the settings of the section in the directory `local_dir`. Returns None if the
+    /// directory is not a section.
+    pub fn load(root: &Root, local_dir: &str) -> MyResult<Option<Section>> {
+        let path = root.path(&format!("{}/.wikid.json", local_dir));
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(_) => return Ok(None)
+        };
+        let save_section = match serde_json::from_str(&text) {
+            Ok(s) => s,
+            Err(e) => return Err(format!("{} was corrupted ({})", path, e).into())
+        };
+        Ok(Some(Section::from_save_section(save_section, local_dir)))
+    }
 }
 
 
 impl AddSettings {
-    pub fn run(&self) -> MyResult<()> {
-        unsafe {
-            crate::VERBOSE = self.verbose;
-        }
-        let color = match Color::from_str(self.color.trim_end()) {
-            Ok(c) => c,
-            Err(_) => return Err("Please give a proper hex-formatted color (e.g., #abcdef).".to_owned())
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let root = Root::summon(chosen)?;
+        let color = match &self.color {
+            Some(c) => match Color::from_str(c.trim_end()) {
+                Ok(c) => c.to_string(),
+                Err(_) => return Err("Please give a proper hex-formatted color (e.g., #abcdef).".into())
+            },
+            None => String::new(),
         };
+        let name = self.name.trim_matches('/');
+        let local_dir = format!("text/{}", name);
+        if name.is_empty() || normalize(name) != name {
+            return Err("Give the section as a path inside text/, such as physics/qm".into());
+        }
+        if !Path::new(&root.path(parent_dir(&local_dir))).is_dir() {
+            return Err(format!("There is no section {}. Create it first", &parent_dir(&local_dir)[5..]).into());
+        }
+
+        let section = Section::new(&root, &local_dir, color, self.draft)?;
     
-        Section::new(self.name.clone(), color)?;
-    
-        // Add to the toc
+        // Add to the toc of the containing section
         {
             let mut file = match OpenOptions::new()
-            .write(true)
             .append(true)
-            .open(Root::get_path_from_local("text/_toc.md")?) {
+            .open(root.path(&format!("{}/_toc.md", parent_dir(&local_dir)))) {
                 Ok(f) => f,
-                Err(_) => return Err("Could not open table of contents".to_owned())
+                Err(_) => return Err("Could not open table of contents".into())
             };
     
-            if let Err(_) = file.write_all(&format!("* [{0}]{{{0}/_toc}}\n", self.name).as_bytes()) {
-                return Err("Could not add section name to toc.".to_owned());
+            if file.write_all(format!("* [{}]{{{}/_toc}}\n", section.name, name).as_bytes()).is_err() {
+                return Err("Could not add section name to toc.".into());
             }
         }
     
@@ -127,48 +250,510 @@ impl AddSettings {
     }
 }
 
-// impl RmSettings {
-//     pub fn run(&self) -> MyResult<()> {
-//         let files = match fs::read_dir(Root::get_path_from_local(&format!("text/{}", self.name))?) {
-//             Ok(p) => p,
-//             Err(_) => return Err("That section did not exist".to_owned())
-//         };
-
-//         // Check if there are files in the section
-//         if !self.force {
-//             let mut directory_is_full = false;
-//             for file in files {
-//                 let file_name = match file {
-//                     Ok(f) => f.file_name(),
-//                     Err(_) => return Err("Could not read all the files in the section directory".to_owned())
-//                 };
-//                 if file_name != "_toc.md" {
-//                     println!("Encountered file {:?} in section {}", file_name, self.name);
-//                     directory_is_full = true;
-//                 }
-//             }
-//             if directory_is_full {
-//                 return Err("Cannot delete a full directory without a force".to_owned())
-//             }
-//         }
-
-//         // Delete the section
-//         let mut root = Root::summon()?;
-
-//         if let Err(_) = remove_dir_all(Root::get_path_from_local(&format!("text/{}", self.name))?) {
-//             return Err("Cannot remove the section file".to_owned());
-//         };
-//         root.write()?;
-
-//         println!("Section successfully removed");
-
-//         Ok(())
-//     }
-// }
-
-// pub fn list_sections() -> MyResult<()> {
-//     for sec in Root::summon()?.sections {
-//         println!("{}", sec.name);
-//     }
-//     Ok(())
-// }
+/// Title for a page without one, made from its file name
+fn title_from_name(name: &str) -> String {
+    let words = name.replace(['_', '-'], " ");
+    let mut chars = words.trim().chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl NewSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let root = Root::summon(chosen)?;
+        let name = self.name.trim_end_matches(".md");
+        let local_path = format!("text/{}.md", name);
+        if name.is_empty() || normalize(name) != name {
+            return Err("Give the page as a path inside text/, such as physics/spin".into());
+        }
+        let global_path = root.path(&local_path);
+        if Path::new(&global_path).exists() {
+            return Err(format!("{} already exists", local_path).into());
+        }
+        let dir = parent_dir(&local_path);
+        if !Path::new(&root.path(dir)).is_dir() {
+            return Err(format!("There is no section {}. Create it first with wikid add", &dir[5..]).into());
+        }
+
+        let editor = match (self.edit, std::env::var("EDITOR")) {
+            (false, _) => None,
+            (true, Ok(e)) if !e.trim().is_empty() => Some(e),
+            (true, _) => return Err("Set the EDITOR environment variable to open the page".into())
+        };
+
+        let title = match &self.title {
+            Some(t) => t.trim().to_owned(),
+            None => title_from_name(Path::new(name).file_name().and_then(|n| n.to_str()).unwrap_or(name)),
+        };
+        // Quoted as JSON, which YAML reads too, so that titles such as "Spin: a primer" stay strings
+        let quoted = serde_json::to_string(&title).expect("Title was not serializable");
+        let text = format!("---\ntitle: {quoted}\ndate: {date}\n---\n# {title}\n\n", quoted=quoted, title=title, date=chrono::Utc::now().format("%Y-%m-%d"));
+        if fs::write(&global_path, text).is_err() {
+            return Err(format!("Could not create {}", local_path).into());
+        }
+
+        // Add to the section's toc
+        {
+            let toc_path = root.path(&format!("{}/_toc.md", dir));
+            let toc = fs::read_to_string(&toc_path).unwrap_or_default();
+            let mut file = match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&toc_path) {
+                Ok(f) => f,
+                Err(_) => return Err("Could not open table of contents".into())
+            };
+            let separator = match toc.is_empty() || toc.ends_with('\n') {
+                true => "",
+                false => "\n",
+            };
+            if file.write_all(format!("{}* [{}]{{{}}}\n", separator, title, name).as_bytes()).is_err() {
+                return Err("Could not add page to toc.".into());
+            }
+        }
+
+        println!("Created {}", local_path);
+
+        if let Some(editor) = editor {
+            let mut words = editor.split_whitespace();
+            let program = words.next().expect("Editor was empty");
+            match std::process::Command::new(program).args(words).arg(&global_path).status() {
+                Ok(s) if s.success() => (),
+                _ => return Err(format!("Could not open {} with {}", local_path, editor).into())
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns true if the link `target` in the page at `source` refers to one of `labels`
+pub(crate) fn refers_to(target: &str, source: &str, labels: &HashSet<String>) -> bool {
+    target_keys(target, source).iter().any(|k| labels.contains(k))
+}
+
+/// Remove the lines of the table of contents at `toc_path` which link to any of `labels`.
+fn remove_toc_entries(root: &Root, toc_path: &str, labels: &HashSet<String>) -> MyResult<()> {
+    let global_path = root.path(toc_path);
+    let text = match fs::read_to_string(&global_path) {
+        Ok(t) => t,
+        Err(_) => return Ok(())// No table of contents
+    };
+    let mut kept = String::new();
+    for line in text.lines() {
+        if find_link_targets(line).iter().any(|t| !t.image && refers_to(&t.target, toc_path, labels)) {
+            if crate::is_verbose() {
+                println!("Removed \"{}\" from {}", line, toc_path);
+            }
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if kept != text && fs::write(&global_path, kept).is_err() {
+        return Err(format!("Could not write {}", toc_path).into());
+    }
+    Ok(())
+}
+
+impl RmSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let name = self.name.trim_end_matches('/').trim_end_matches(".md");
+        // Paths such as .. or . would reach text/ itself or the rest of the wiki
+        if name.is_empty() || normalize(name) != name {
+            return Err("Give the section or page as a path inside text/, such as physics or physics/spin".into());
+        }
+        let ctx = Context::load(chosen, false, true)?;
+        let root = &ctx.root;
+        let dir = format!("text/{}", name);
+        let is_section = Path::new(&root.path(&dir)).is_dir();
+        let local_paths = Node::new(&ctx)?.local_paths();
+        let removed = match is_section {
+            true => local_paths.iter().filter(|p| p.starts_with(&format!("{}/", dir))).cloned().collect::<Vec<_>>(),
+            false => local_paths.iter().filter(|p| **p == format!("{}.md", dir)).cloned().collect::<Vec<_>>(),
+        };
+        if !is_section && removed.is_empty() {
+            return Err(format!("There is no section or page {}", self.name).into());
+        }
+
+        // Check if there are pages in the section
+        if is_section && !self.force {
+            let pages = removed.iter().filter(|p| **p != format!("{}/_toc.md", dir)).collect::<Vec<_>>();
+            for page in &pages {
+                println!("Encountered page {} in section {}", page, name);
+            }
+            if !pages.is_empty() {
+                return Err("Cannot remove a section which still contains pages without --force".into());
+            }
+        }
+
+        // Labels defined in the removed content, including the pages themselves
+        let mut labels = HashSet::new();
+        for path in &removed {
+            labels.insert(path[5..path.len()-3].to_owned());
+            labels.extend(Page::read(path, &ctx)?.labels);
+        }
+
+        // Remove the entries in the root and parent tables of contents
+        let parent = Path::new(&dir).parent().and_then(|p| p.to_str()).unwrap_or("text").to_owned();
+        remove_toc_entries(root, "text/_toc.md", &labels)?;
+        if parent != "text" {
+            remove_toc_entries(root, &format!("{}/_toc.md", parent), &labels)?;
+        }
+
+        let global_path = match is_section {
+            true => root.path(&dir),
+            false => root.path(&format!("{}.md", dir)),
+        };
+        let result = match is_section {
+            true => fs::remove_dir_all(&global_path),
+            false => fs::remove_file(&global_path),
+        };
+        if result.is_err() {
+            return Err(format!("Could not remove {}", global_path).into());
+        }
+
+        // Report the links which are now broken
+        let mut report = Report::new();
+        for path in local_paths.iter().filter(|p| !removed.contains(p)) {
+            let text = match fs::read_to_string(root.path(path)) {
+                Ok(t) => t,
+                Err(_) => continue// Removed along with its table of contents entry
+            };
+            let lines = text.lines().collect::<Vec<_>>();
+            for target in find_link_targets(&text) {
+                if target.image || !refers_to(&target.target, path, &labels) {
+                    continue;
+                }
+                let line = lines[target.line - 1];
+                report.add(Diagnostic::warning(format!("Link {} points to removed content", target.target))
+                    .code(codes::DANGLING_LINK)
+                    .in_file(path)
+                    .at_line(target.line, line)
+                    .at_column(line[..target.start].chars().count() + 1, target.target.chars().count()));
+            }
+        }
+        report.print();
+
+        println!("Removed {}. {} links to it remain", name, report.warnings());
+        Ok(())
+    }
+}
+
+/// Resolve the `.` and `..` components of a path
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => { parts.pop(); },
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Path of `path` relative to the directory `dir`
+fn relative(dir: &str, path: &str) -> String {
+    let dir = dir.split('/').collect::<Vec<_>>();
+    let path = path.split('/').collect::<Vec<_>>();
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    let mut parts = vec![".."; dir.len() - common];
+    parts.extend(&path[common..]);
+    parts.join("/")
+}
+
+/// Directory containing the file at `local_path`
+fn parent_dir(local_path: &str) -> &str {
+    Path::new(local_path).parent().and_then(|p| p.to_str()).unwrap_or("text")
+}
+
+/// Path of the section containing the file at `local_path` from text/, empty at the top
+fn section_of(local_path: &str) -> &str {
+    parent_dir(local_path).get(5..).unwrap_or("")
+}
+
+/// Label of the page at `local_path`, which is its path without text/ or .md
+fn page_key(local_path: &str) -> String {
+    local_path[5..local_path.len()-3].to_owned()
+}
+
+/// The page which the link `target` in the page at `source` points to, if it points to a page
+fn resolve(target: &str, source: &str, keys: &HashSet<String>) -> Option<String> {
+    target_keys(target, source).into_iter().find(|k| keys.contains(k))
+}
+
+/// The table of contents entry `line` of `from_toc`, if it links to `key`, rewritten to link to
+/// `key` from `to_toc`. `names` renames the display text of a section from the first name to the
+/// second.
+fn retarget_toc_entry(line: &str, from_toc: &str, to_toc: &str, key: &str, keys: &HashSet<String>, names: Option<(&str, &str)>) -> Option<String> {
+    let targets = find_link_targets(line).into_iter()
+        .filter(|t| !t.image && resolve(&t.target, from_toc, keys).as_deref() == Some(key))
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        return None;
+    }
+    let mut entry = line.to_owned();
+    // From the end of the line, so that earlier offsets stay correct
+    for target in targets.into_iter().rev() {
+        let section_relative = key.strip_prefix(&format!("{}/", section_of(to_toc)));
+        if let Some(c) = [Some(key), section_relative].iter().flatten().find(|c| resolve(c, to_toc, keys).as_deref() == Some(key)) {
+            entry.replace_range(target.start..target.start + target.target.len(), c);
+        }
+    }
+    if let Some((old, new)) = names {
+        entry = entry.replacen(&format!("[{}]", old), &format!("[{}]", new), 1);
+    }
+    Some(entry)
+}
+
+/// Move the entries of the table of contents `from_toc` which link to `key` to the end of `to_toc`,
+/// or rewrite them in place if they are the same. See `retarget_toc_entry`.
+fn move_toc_entries(root: &Root, from_toc: &str, to_toc: &str, key: &str, keys: &HashSet<String>, names: Option<(&str, &str)>) -> MyResult<()> {
+    let from_global = root.path(from_toc);
+    let text = match fs::read_to_string(&from_global) {
+        Ok(t) => t,
+        Err(_) => return Ok(())// No table of contents
+    };
+    let mut kept = Vec::new();
+    let mut moved = Vec::new();
+    for line in text.split('\n') {
+        match retarget_toc_entry(line, from_toc, to_toc, key, keys, names) {
+            Some(entry) if from_toc != to_toc => moved.push(entry),
+            Some(entry) => kept.push(entry),
+            None => kept.push(line.to_owned()),
+        }
+    }
+    let kept = kept.join("\n");
+    if kept != text && fs::write(&from_global, kept).is_err() {
+        return Err(format!("Could not write {}", from_toc).into());
+    }
+    if moved.is_empty() {
+        return Ok(());
+    }
+    let to_global = root.path(to_toc);
+    let mut toc = fs::read_to_string(&to_global).unwrap_or_default();
+    if !toc.is_empty() && !toc.ends_with('\n') {
+        toc.push('\n');
+    }
+    for entry in moved {
+        if crate::is_verbose() {
+            println!("Moved \"{}\" to {}", entry, to_toc);
+        }
+        toc.push_str(&entry);
+        toc.push('\n');
+    }
+    if fs::write(&to_global, toc).is_err() {
+        return Err(format!("Could not write {}", to_toc).into());
+    }
+    Ok(())
+}
+
+impl MvSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        crate::set_verbose(self.verbose);
+        let from = self.from.trim_end_matches('/').trim_end_matches(".md");
+        let to = self.to.trim_end_matches('/').trim_end_matches(".md");
+        let from_dir = format!("text/{}", from);
+        let to_dir = format!("text/{}", to);
+        if from.is_empty() || to.is_empty() || normalize(from) != from || normalize(to) != to {
+            return Err("Give both paths inside text/, such as physics/spin".into());
+        }
+        let ctx = Context::load(chosen, false, true)?;
+        let is_section = Path::new(&ctx.path(&from_dir)).is_dir();
+        let (from_path, to_path) = match is_section {
+            true => (from_dir.clone(), to_dir.clone()),
+            false => (format!("{}.md", from_dir), format!("{}.md", to_dir)),
+        };
+        if !Path::new(&ctx.path(&from_path)).exists() {
+            return Err(format!("There is no section or page {}", self.from).into());
+        }
+        if Path::new(&ctx.path(&to_path)).exists() {
+            return Err(format!("{} already exists", to_path).into());
+        }
+        if is_section && to_dir.starts_with(&format!("{}/", from_dir)) {
+            return Err("Cannot move a section inside itself".into());
+        }
+        if !Path::new(&ctx.path(parent_dir(&to_path))).is_dir() {
+            return Err(format!("There is no section {}. Create it first with wikid add", &parent_dir(&to_path)[5..]).into());
+        }
+
+        // Where every page will be after the move
+        let local_paths = Node::new(&ctx)?.local_paths();
+        let moved = |path: &str| -> String {
+            match path.strip_prefix(&format!("{}/", from_dir)) {
+                Some(rest) if is_section => format!("{}/{}", to_dir, rest),
+                _ if path == from_path => to_path.clone(),
+                _ => path.to_owned(),
+            }
+        };
+        let new_paths = local_paths.iter().map(|p| (p.clone(), moved(p))).collect::<HashMap<_, _>>();
+        let old_keys = local_paths.iter().map(|p| page_key(p)).collect::<HashSet<_>>();
+        let new_keys = new_paths.values().map(|p| page_key(p)).collect::<HashSet<_>>();
+        let key_map = new_paths.iter().map(|(o, n)| (page_key(o), page_key(n))).collect::<HashMap<_, _>>();
+
+        // Rewrite the links in every page, including those being moved, before moving anything
+        let mut report = Report::new();
+        let mut rewritten = 0;
+        for old_path in &local_paths {
+            let new_path = &new_paths[old_path];
+            let global_path = ctx.path(old_path);
+            let text = match fs::read_to_string(&global_path) {
+                Ok(t) => t,
+                Err(_) => return Err(format!("Could not read {}", old_path).into())
+            };
+            let mut lines = text.split('\n').map(|l| l.to_owned()).collect::<Vec<_>>();
+            let mut changed = false;
+            // Targets are rewritten from the end of the line so that earlier offsets stay correct
+            for target in find_link_targets(&text).into_iter().rev() {
+                let replacement = if target.image {
+                    let (path, args) = target.target.split_at(target.target.find('?').unwrap_or(target.target.len()));
+                    let image = normalize(&format!("{}/{}", parent_dir(old_path), path));
+                    let new_image = match image.strip_prefix(&format!("{}/", from_dir)) {
+                        Some(rest) if is_section => format!("{}/{}", to_dir, rest),
+                        _ => image,
+                    };
+                    let new_target = format!("{}{}", relative(parent_dir(new_path), &new_image), args);
+                    if new_target == target.target {
+                        continue;
+                    }
+                    Ok(new_target)
+                } else {
+                    let old_key = match resolve(&target.target, old_path, &old_keys) {
+                        Some(k) => k,
+                        None => continue// A label, which moves with its page, or a broken link
+                    };
+                    let new_key = &key_map[&old_key];
+                    if resolve(&target.target, new_path, &new_keys).as_ref() == Some(new_key) {
+                        continue;
+                    }
+                    // Keep links which were relative to the page's section relative if possible
+                    let mut candidates = Vec::new();
+                    if target_keys(&target.target, old_path)[0] == old_key {
+                        if let Some(rest) = new_key.strip_prefix(&format!("{}/", section_of(new_path))) {
+                            candidates.push(rest.to_owned());
+                        }
+                    }
+                    candidates.push(new_key.clone());
+                    match candidates.into_iter().find(|c| resolve(c, new_path, &new_keys).as_ref() == Some(new_key)) {
+                        Some(c) => Ok(c),
+                        None => Err(new_key.clone()),
+                    }
+                };
+                let line = &mut lines[target.line - 1];
+                match replacement {
+                    Ok(r) => {
+                        if crate::is_verbose() {
+                            println!("{}:{}: {} -> {}", old_path, target.line, target.target, r);
+                        }
+                        line.replace_range(target.start..target.start + target.target.len(), &r);
+                        changed = true;
+                        rewritten += 1;
+                    },
+                    Err(new_key) => report.add(Diagnostic::warning(format!("Link {} could not be rewritten", target.target))
+                        .code(codes::DANGLING_LINK)
+                        .in_file(old_path)
+                        .at_line(target.line, line)
+                        .at_column(line[..target.start].chars().count() + 1, target.target.chars().count())
+                        .help(format!("Link to the page as {}", new_key))),
+                };
+            }
+            if changed && fs::write(&global_path, lines.join("\n")).is_err() {
+                return Err(format!("Could not write {}", old_path).into());
+            }
+        }
+
+        if fs::rename(ctx.path(&from_path), ctx.path(&to_path)).is_err() {
+            return Err(format!("Could not move {} to {}", from_path, to_path).into());
+        }
+
+        // List the moved content in the table of contents of its new section, under its new name
+        let (key, names) = match is_section {
+            true => {
+                let name = |path: &str| Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path).to_owned();
+                (format!("{}/_toc", to), Some((name(from), name(to))))
+            },
+            false => (to.to_owned(), None),
+        };
+        move_toc_entries(&ctx.root, &format!("{}/_toc.md", parent_dir(&from_path)), &format!("{}/_toc.md", parent_dir(&to_path)),
+            &key, &new_keys, names.as_ref().map(|(o, n)| (o.as_str(), n.as_str())))?;
+
+        // Keep earlier redirects pointing at the pages' new addresses
+        let mut root = ctx.root;
+        let html_paths = new_paths.iter()
+            .filter(|(o, n)| o != n)
+            .map(|(o, n)| (html_path(o)[5..].to_owned(), html_path(n)[5..].to_owned()))
+            .collect::<HashMap<_, _>>();
+        let redirects = root.redirects.clone();
+        for target in root.redirects.values_mut() {
+            if let Some(new_target) = html_paths.get(target) {
+                *target = new_target.clone();
+            }
+        }
+        root.redirects.retain(|from, _| !html_paths.values().any(|n| n == from));
+        if self.redirect {
+            root.redirects.extend(html_paths.clone());
+        }
+        if root.redirects != redirects {
+            root.write()?;
+        }
+
+        report.print();
+        println!("Moved {} to {}. Rewrote {} links", from, to, rewritten);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> HashSet<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn normalize_resolves_dots() {
+        assert_eq!(normalize("text/physics/../chem/./img.png"), "text/chem/img.png");
+        assert_eq!(normalize("/a//b/"), "a/b");
+    }
+
+    #[test]
+    fn relative_walks_up_to_the_common_directory() {
+        assert_eq!(relative("text/physics", "text/physics/img.png"), "img.png");
+        assert_eq!(relative("text/physics/qm", "text/chem/img.png"), "../../chem/img.png");
+    }
+
+    #[test]
+    fn resolve_prefers_the_section_of_the_source() {
+        let keys = keys(&["physics/spin", "spin"]);
+        assert_eq!(resolve("spin", "text/physics/atoms.md", &keys).as_deref(), Some("physics/spin"));
+        assert_eq!(resolve("spin", "text/home.md", &keys).as_deref(), Some("spin"));
+        assert_eq!(resolve("missing", "text/home.md", &keys), None);
+    }
+
+    #[test]
+    fn toc_entry_moves_to_another_section() {
+        let keys = keys(&["chem/bar", "chem/_toc", "physics/_toc"]);
+        let entry = retarget_toc_entry("* [Foo]{chem/bar}", "text/physics/_toc.md", "text/chem/_toc.md", "chem/bar", &keys, None);
+        assert_eq!(entry.as_deref(), Some("* [Foo]{chem/bar}"));
+        assert_eq!(retarget_toc_entry("* [Other]{physics/other}", "text/physics/_toc.md", "text/chem/_toc.md", "chem/bar", &keys, None), None);
+    }
+
+    #[test]
+    fn toc_entry_of_a_renamed_section_is_renamed() {
+        let keys = keys(&["physics/quantum/_toc", "physics/_toc"]);
+        let entry = retarget_toc_entry("* [qm]{physics/quantum/_toc}", "text/physics/_toc.md", "text/physics/_toc.md",
+            "physics/quantum/_toc", &keys, Some(("qm", "quantum")));
+        assert_eq!(entry.as_deref(), Some("* [quantum]{physics/quantum/_toc}"));
+    }
+
+    #[test]
+    fn title_from_name_capitalizes() {
+        assert_eq!(title_from_name("spin_orbit-coupling"), "Spin orbit coupling");
+    }
+}
@@ -0,0 +1,141 @@
+use clap::{Parser, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::constants::*;
+use crate::root::Root;
+
+/// Themes built into wikid, which an installed theme of the same name replaces
+const BUILTIN_THEMES: [(&str, &str); 2] = [
+    ("default", include_str!("themes/default.css")),
+    ("paper", include_str!("themes/paper.css")),
+];
+
+#[derive(Parser)]
+pub struct ThemeSettings {
+    #[command(subcommand)]
+    command: ThemeCommand,
+}
+
+#[derive(Subcommand)]
+enum ThemeCommand {
+    /// Print the built-in and installed themes
+    List,
+    /// Install a directory of CSS templates as a theme of this wiki
+    Install {
+        /// Directory containing the theme's .css files
+        dir: String,
+        /// Name of the theme. Defaults to the name of the directory
+        #[arg(long)]
+        name: Option<String>,
+        /// Replace an installed theme of the same name
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+/// The .css files directly inside `dir`, in order of name
+fn css_files(dir: &Path) -> MyResult<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return Err(format!("Could not read the theme directory {}", dir.display()).into())
+    };
+    let mut files = entries.filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "css"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Directory of the installed themes
+fn themes_dir(root: &Root) -> String {
+    root.path("themes")
+}
+
+/// The template of the theme `name`: the .css files of the installed theme in order, or the
+/// built-in theme.
+pub(crate) fn template(name: &str, root: &Root) -> MyResult<String> {
+    let dir = PathBuf::from(format!("{}/{}", themes_dir(root), name));
+    if dir.is_dir() {
+        let mut out = String::new();
+        for file in css_files(&dir)? {
+            match fs::read_to_string(&file) {
+                Ok(t) => out.push_str(&t),
+                Err(_) => return Err(format!("Could not read the theme file {}", file.display()).into())
+            };
+        }
+        if out.is_empty() {
+            return Err(format!("The theme {} has no .css files", name).into());
+        }
+        return Ok(out);
+    }
+    match BUILTIN_THEMES.iter().find(|(n, _)| *n == name) {
+        Some((_, t)) => Ok(t.to_string()),
+        None => Err(format!("Unknown theme {}. Install it with wikid theme install, or use one of {}",
+            name, BUILTIN_THEMES.map(|(n, _)| n).join(", ")).into())
+    }
+}
+
+impl ThemeSettings {
+    pub fn run(&self, chosen: Option<&str>) -> MyResult<()> {
+        let root = Root::summon(chosen)?;
+        match &self.command {
+            ThemeCommand::List => {
+                let mut installed = Vec::new();
+                if let Ok(entries) = fs::read_dir(themes_dir(&root)) {
+                    for entry in entries.filter_map(|e| e.ok()) {
+                        if entry.path().is_dir() {
+                            installed.push(entry.file_name().to_string_lossy().to_string());
+                        }
+                    }
+                }
+                installed.sort();
+                for (name, _) in BUILTIN_THEMES {
+                    if !installed.iter().any(|n| n == name) {
+                        println!("{}  built-in", name);
+                    }
+                }
+                for name in installed {
+                    println!("{}  installed", name);
+                }
+            },
+            ThemeCommand::Install { dir, name, force } => {
+                let source = Path::new(dir);
+                let name = match name {
+                    Some(n) => n.clone(),
+                    None => match source.canonicalize().ok().and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string())) {
+                        Some(n) => n,
+                        None => return Err(format!("Could not name the theme in {}. Use --name", dir).into())
+                    }
+                };
+                if name.is_empty() || name.starts_with('.') || name.contains('/') {
+                    return Err(format!("{} is not a valid theme name", name).into());
+                }
+                let files = css_files(source)?;
+                if files.is_empty() {
+                    return Err(format!("{} contains no .css files", dir).into());
+                }
+                let target = format!("{}/{}", themes_dir(&root), name);
+                if Path::new(&target).exists() {
+                    if !force {
+                        return Err(format!("The theme {} is already installed. Use --force to replace it", name).into());
+                    }
+                    if fs::remove_dir_all(&target).is_err() {
+                        return Err(format!("Could not remove the installed theme {}", name).into());
+                    }
+                }
+                if fs::create_dir_all(&target).is_err() {
+                    return Err(format!("Could not create the theme directory {}", target).into());
+                }
+                for file in files {
+                    let file_name = file.file_name().expect("Theme file had no name");
+                    if fs::copy(&file, Path::new(&target).join(file_name)).is_err() {
+                        return Err(format!("Could not copy the theme file {}", file.display()).into());
+                    }
+                }
+                println!("Installed the theme {}. Use it with wikid config set theme {}", name, name);
+            },
+        };
+        Ok(())
+    }
+}